@@ -0,0 +1,440 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use csv::WriterBuilder;
+use serde::Serialize;
+use std::fs::File;
+use std::ops::Bound;
+use std::path::Path;
+
+use crate::error::BatteryError;
+use crate::predict::Prediction;
+use crate::predict::PredictionOptions;
+use crate::predictor::Predictor;
+use crate::session::segment_sessions_with;
+use crate::session::Session;
+use crate::session::SessionKind;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::CapacityUnit;
+
+/// Where and how far [backtest] checks the predictions.
+#[derive(Debug, Clone)]
+pub struct BacktestOptions {
+    /// time between the points at which the predictions are made
+    pub interval: Duration,
+    /// history before the first point, so that the predictors have earlier sessions to learn from
+    pub warm_up: Duration,
+    /// the predicted capacity is compared with the records up to this long after each point
+    pub horizon: Duration,
+    /// the sessions which the predicted time to empty or full is compared with are segmented like this,
+    /// and the predictions are made up to its maximum horizon
+    pub prediction: PredictionOptions,
+}
+
+impl Default for BacktestOptions {
+    fn default() -> Self {
+        BacktestOptions {
+            interval: Duration::hours(1),
+            warm_up: Duration::days(1),
+            horizon: Duration::hours(6),
+            prediction: PredictionOptions::default(),
+        }
+    }
+}
+
+/// Error metrics of a predictor over all the points of the backtest.
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestResult {
+    pub predictor: String,
+    /// number of points at which a prediction was made
+    pub points: usize,
+    /// number of points at which the predictor returned an error
+    pub failures: usize,
+    /// mean absolute error in minutes of the time to the end of the discharging sessions
+    pub time_to_empty_mae: Option<f64>,
+    pub time_to_empty_samples: usize,
+    /// mean absolute error in minutes of the time to the end of the charging sessions
+    pub time_to_full_mae: Option<f64>,
+    pub time_to_full_samples: usize,
+    /// root mean square error of the predicted capacity, in the unit of the history
+    pub capacity_rmse: Option<f64>,
+    pub capacity_samples: usize,
+    /// share of the compared capacities which are within the [crate::PredictionInterval], between 0 and 1
+    pub interval_coverage: Option<f64>,
+    pub unit: CapacityUnit,
+}
+
+/// Results of each predictor of [backtest].
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestReport {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub results: Vec<BacktestResult>,
+}
+
+impl BacktestReport {
+    /// Writes one row for each predictor, with headers
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), BatteryError> {
+        let mut wtr = WriterBuilder::new().has_headers(true).from_path(path)?;
+        for result in self.results.iter() {
+            wtr.serialize(result)?;
+        }
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), BatteryError> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+
+        Ok(())
+    }
+}
+
+/// Absolute errors of the times and squared errors of the capacities of a predictor
+#[derive(Default)]
+struct Errors {
+    points: usize,
+    failures: usize,
+    time_to_empty: Vec<f64>,
+    time_to_full: Vec<f64>,
+    capacity: Vec<f64>,
+    /// 1.0 for each compared capacity within the interval, 0.0 otherwise
+    covered: Vec<f64>,
+}
+
+/// Replays the history, at each point predicting with only the records up to it and comparing the
+/// prediction with what actually happened.
+///
+/// As most of the discharging sessions end by plugging in rather than by running out, the time to empty
+/// is measured as the time to reach the capacity at which the discharging session actually ended, and
+/// the time to full as the time to reach the capacity at which the charging session ended. The points at
+/// which the predictor expects the other kind of session, or doesn't reach that capacity within its horizon,
+/// are only counted for the capacity.
+///
+/// # Parameters
+/// data: the history, e.g. returned by [crate::read_data::get_data] or [crate::read_data::get_log]
+///
+/// predictors: the predictors along with their names for the report, each is fitted on the history
+/// up to every point
+///
+/// options: see [BacktestOptions]
+///
+/// # Returns
+/// The [BacktestReport], or the error when the history is empty or its capacities can't be converted
+/// into the same unit.
+pub fn backtest(
+    data: &BatteryTimeSeries,
+    predictors: &mut [(&str, &mut dyn Predictor)],
+    options: &BacktestOptions,
+) -> Result<BacktestReport, BatteryError> {
+    let last = data.last().ok_or(BatteryError::EmptyData)?;
+    let first = data.first().unwrap();
+    let unit = last.capacity.unit();
+
+    if options.interval <= Duration::zero() {
+        return Err(BatteryError::InvalidOption(
+            "The interval of the backtest must be positive".to_string(),
+        ));
+    }
+
+    // the predictions of the histories up to each point are all in the unit of the last record
+    let history = data.to_unit(unit)?;
+
+    let sessions = segment_sessions_with(&history, &options.prediction.segmentation)?;
+
+    let mut errors: Vec<Errors> = predictors.iter().map(|_| Errors::default()).collect();
+
+    let mut past = BatteryTimeSeries::new();
+    // the sessions are in time order, so the search for the session of each point starts from the previous one
+    let mut session_index = 0;
+
+    let mut point = first.date_time + options.warm_up;
+    while point < last.date_time {
+        // only the records since the previous point are added
+        let from = past
+            .last()
+            .map_or(Bound::Unbounded, |record| Bound::Excluded(record.date_time));
+        past.extend(history.range((from, Bound::Included(point))).cloned());
+        point += options.interval;
+
+        let start = match past.last() {
+            Some(record) => record.date_time,
+            None => continue,
+        };
+        let future: Vec<(DateTime<Utc>, f64)> = history
+            .range(start..=start + options.horizon)
+            .skip(1)
+            .map(|record| (record.date_time, record.capacity.value()))
+            .collect();
+        while sessions
+            .get(session_index)
+            .is_some_and(|session| session.end <= start)
+        {
+            session_index += 1;
+        }
+        let session = sessions
+            .get(session_index)
+            .filter(|session| session.start <= start);
+
+        for ((_, predictor), errors) in predictors.iter_mut().zip(errors.iter_mut()) {
+            errors.points += 1;
+
+            let prediction = match predictor
+                .fit(&past)
+                .and_then(|_| predictor.forecast(options.prediction.max_horizon))
+            {
+                Ok(prediction) => prediction,
+                Err(_) => {
+                    errors.failures += 1;
+                    continue;
+                }
+            };
+
+            for (date_time, capacity) in future.iter() {
+                if let Some(predicted) = capacity_at(&prediction.predicted_data, *date_time) {
+                    errors.capacity.push((predicted - capacity).powi(2));
+                }
+                if let (Some(lower), Some(upper)) = (
+                    capacity_at(&prediction.interval.lower, *date_time),
+                    capacity_at(&prediction.interval.upper, *date_time),
+                ) {
+                    let covered = lower <= *capacity && *capacity <= upper;
+                    errors.covered.push(if covered { 1.0 } else { 0.0 });
+                }
+            }
+
+            if let Some(error) = session.and_then(|session| time_error(&prediction, session)) {
+                match prediction.kind {
+                    SessionKind::Charging => errors.time_to_full.push(error),
+                    _ => errors.time_to_empty.push(error),
+                }
+            }
+        }
+    }
+
+    let mean = |values: &[f64]| {
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.iter().sum::<f64>() / values.len() as f64)
+        }
+    };
+
+    Ok(BacktestReport {
+        start: first.date_time,
+        end: last.date_time,
+        results: predictors
+            .iter()
+            .zip(errors)
+            .map(|((name, _), errors)| BacktestResult {
+                predictor: name.to_string(),
+                points: errors.points,
+                failures: errors.failures,
+                time_to_empty_mae: mean(&errors.time_to_empty),
+                time_to_empty_samples: errors.time_to_empty.len(),
+                time_to_full_mae: mean(&errors.time_to_full),
+                time_to_full_samples: errors.time_to_full.len(),
+                capacity_rmse: mean(&errors.capacity).map(f64::sqrt),
+                capacity_samples: errors.capacity.len(),
+                interval_coverage: mean(&errors.covered),
+                unit,
+            })
+            .collect(),
+    })
+}
+
+/// Linearly interpolated capacity of the prediction at given time, the battery stays empty or full
+/// after the end of the prediction. [None] before the start of the prediction.
+fn capacity_at(predicted: &BatteryTimeSeries, date_time: DateTime<Utc>) -> Option<f64> {
+    let before = predicted.at_or_before(date_time)?;
+
+    match predicted.at_or_after(date_time) {
+        Some(after) if after.date_time > before.date_time => {
+            let fraction = (date_time - before.date_time).num_seconds() as f64
+                / (after.date_time - before.date_time).num_seconds() as f64;
+            Some(
+                before.capacity.value()
+                    + (after.capacity.value() - before.capacity.value()) * fraction,
+            )
+        }
+        _ => Some(before.capacity.value()),
+    }
+}
+
+/// Absolute error in minutes of the predicted time to the capacity at the end of the session
+fn time_error(prediction: &Prediction, session: &Session) -> Option<f64> {
+    if prediction.kind != session.kind || session.end <= prediction.start {
+        return None;
+    }
+
+    let target = session.end_capacity.value();
+    let records: Vec<(DateTime<Utc>, f64)> = prediction
+        .predicted_data
+        .iter()
+        .map(|record| (record.date_time, record.capacity.value()))
+        .collect();
+
+    // the first step of the prediction which reaches the capacity
+    let predicted_end = records.windows(2).find_map(|pair| {
+        let ((from_date, from), (to_date, to)) = (pair[0], pair[1]);
+        if (target - from) * (target - to) > 0.0 || from == to {
+            return None;
+        }
+
+        let fraction = (target - from) / (to - from);
+        Some(
+            from_date
+                + Duration::seconds(((to_date - from_date).num_seconds() as f64 * fraction) as i64),
+        )
+    })?;
+
+    Some(((predicted_end - session.end).num_seconds() as f64 / 60.0).abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predict::PredictionInterval;
+    use crate::read_data::BatteryHistoryRecord;
+    use crate::read_data::ChargeState;
+    use crate::test_util::{hour, per_hour};
+    use crate::units::Capacity;
+
+    /// Discharging by 1 mWh per hour from 100 mWh for two days
+    fn history() -> BatteryTimeSeries {
+        per_hour(
+            &(0..=48)
+                .map(|i| (100.0 - i as f64, ChargeState::Discharging))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Predicts discharging twice as fast as the history, with an interval which holds the history
+    /// for the first 3 hours only
+    #[derive(Default)]
+    struct TwiceAsFast {
+        fitted: Vec<BatteryHistoryRecord>,
+        lengths: Vec<usize>,
+    }
+
+    impl Predictor for TwiceAsFast {
+        fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+            self.fitted.push(history.last().unwrap().clone());
+            self.lengths.push(history.len());
+            Ok(())
+        }
+
+        fn forecast(&self, _horizon: Duration) -> Result<Prediction, BatteryError> {
+            let last = self.fitted.last().unwrap();
+            let start = last.capacity.value();
+            let series = |points: &[(f64, f64)]| -> BatteryTimeSeries {
+                points
+                    .iter()
+                    .map(|(hours, delta)| BatteryHistoryRecord {
+                        date_time: last.date_time + Duration::seconds((hours * 3600.0) as i64),
+                        capacity: Capacity::milliwatt_hours(start + delta),
+                        state: ChargeState::Discharging,
+                    })
+                    .collect()
+            };
+
+            Ok(Prediction {
+                kind: SessionKind::Discharging,
+                start: last.date_time,
+                rate: -2.0,
+                time_to_empty: None,
+                time_to_full: None,
+                predicted_data: series(&[(0.0, 0.0), (48.0, -96.0)]),
+                interval: PredictionInterval {
+                    confidence: 0.8,
+                    lower: series(&[(0.0, 0.0), (48.0, -144.0)]),
+                    upper: series(&[(0.0, 0.0), (3.0, -1.5), (48.0, -136.5)]),
+                    earliest: None,
+                    latest: None,
+                },
+            })
+        }
+    }
+
+    struct Failing;
+
+    impl Predictor for Failing {
+        fn fit(&mut self, _history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+            Err(BatteryError::Prediction("always fails".to_string()))
+        }
+
+        fn forecast(&self, _horizon: Duration) -> Result<Prediction, BatteryError> {
+            Err(BatteryError::Prediction("always fails".to_string()))
+        }
+    }
+
+    fn options() -> BacktestOptions {
+        BacktestOptions {
+            interval: Duration::hours(6),
+            warm_up: Duration::hours(24),
+            horizon: Duration::hours(6),
+            prediction: PredictionOptions::default(),
+        }
+    }
+
+    #[test]
+    fn metrics_of_a_known_error() {
+        let mut twice_as_fast = TwiceAsFast::default();
+        let mut failing = Failing;
+
+        let report = backtest(
+            &history(),
+            &mut [
+                ("twice as fast", &mut twice_as_fast),
+                ("failing", &mut failing),
+            ],
+            &options(),
+        )
+        .unwrap();
+
+        assert_eq!((report.start, report.end), (hour(0), hour(48)));
+
+        // predicted at 24, 30, 36 and 42 hours with only the records up to then
+        assert_eq!(twice_as_fast.lengths, [25, 31, 37, 43]);
+
+        let result = &report.results[0];
+        assert_eq!(result.points, 4);
+        assert_eq!(result.failures, 0);
+
+        // the end of the session at 48 hours is predicted after half the time, i.e. 12, 9, 6 and 3 hours early
+        assert_eq!(result.time_to_empty_samples, 4);
+        assert!((result.time_to_empty_mae.unwrap() - 450.0).abs() < 1e-6);
+        assert_eq!(result.time_to_full_samples, 0);
+        assert_eq!(result.time_to_full_mae, None);
+
+        // off by 1 to 6 mWh in the 6 hours after each point
+        assert_eq!(result.capacity_samples, 24);
+        assert!((result.capacity_rmse.unwrap() - (91.0_f64 / 6.0).sqrt()).abs() < 1e-6);
+
+        // within the interval for the first 3 of the 6 hours
+        assert_eq!(result.interval_coverage, Some(0.5));
+
+        let failed = &report.results[1];
+        assert_eq!((failed.points, failed.failures), (4, 4));
+        assert_eq!(failed.capacity_rmse, None);
+        assert_eq!(failed.interval_coverage, None);
+    }
+
+    #[test]
+    fn invalid_options() {
+        let options = BacktestOptions {
+            interval: Duration::zero(),
+            ..options()
+        };
+
+        assert!(matches!(
+            backtest(&history(), &mut [], &options),
+            Err(BatteryError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            backtest(&BatteryTimeSeries::new(), &mut [], &options),
+            Err(BatteryError::EmptyData)
+        ));
+    }
+}
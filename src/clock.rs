@@ -0,0 +1,29 @@
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::time_series::BatteryTimeSeries;
+
+/// Source of the current time, deciding which part of the data is of the past and which is predicted.
+///
+/// [Clock::Fixed] and [Clock::LastSample] make the result independent of the time it is made at,
+/// e.g. for analysing an archived report or for comparing rendered images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Clock {
+    /// the time of this machine
+    #[default]
+    System,
+    Fixed(DateTime<Utc>),
+    /// the time of the last record of the data, the time of this machine when there is no record
+    LastSample,
+}
+
+impl Clock {
+    /// The current time for given data
+    pub fn now(&self, data: &BatteryTimeSeries) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(date_time) => *date_time,
+            Clock::LastSample => data.last().map_or_else(Utc::now, |record| record.date_time),
+        }
+    }
+}
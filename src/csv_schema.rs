@@ -0,0 +1,534 @@
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use csv::ReaderBuilder;
+use csv::StringRecord;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::read_html::local_to_utc;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+use crate::units::CapacityUnit;
+
+/// Column referred to by its header or by its zero based index.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CsvColumns {
+    pub date_time: ColumnRef,
+    pub capacity: ColumnRef,
+    /// when [None], the state of every record is [ChargeState::Unknown]
+    #[serde(default)]
+    pub state: Option<ColumnRef>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EpochUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+/// How the date_time column is written.
+///
+/// e.g. `timestamp = { epoch = "seconds" }`, `timestamp = "rfc3339"` or
+/// `timestamp = { format = "%Y-%m-%d %H:%M:%S" }` in toml.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    Epoch(EpochUnit),
+    Rfc3339,
+    /// [chrono::format::strftime] format, the times without offset are
+    /// in UTC unless [CsvSchema::local_time] is set
+    Format(String),
+}
+
+/// Description of a csv battery log, so that the logs of any tool can be read with [get_data_with_schema].
+///
+/// The defaults describe the csv read by [crate::read_data::get_data].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CsvSchema {
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default = "default_has_headers")]
+    pub has_headers: bool,
+    pub columns: CsvColumns,
+    pub timestamp: TimestampFormat,
+    #[serde(default)]
+    pub local_time: bool,
+    /// the records keep the capacity in this unit
+    pub capacity_unit: CapacityUnit,
+    /// values of the state column and the corresponding states, when empty the
+    /// values are matched with the names of [ChargeState] ignoring case
+    #[serde(default)]
+    pub states: HashMap<String, ChargeState>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_has_headers() -> bool {
+    true
+}
+
+impl Default for CsvSchema {
+    fn default() -> Self {
+        CsvSchema::history()
+    }
+}
+
+impl CsvSchema {
+    /// The csv with headers date_time, capacity, state
+    pub fn history() -> Self {
+        CsvSchema {
+            delimiter: default_delimiter(),
+            has_headers: default_has_headers(),
+            columns: CsvColumns {
+                date_time: ColumnRef::Name("date_time".to_string()),
+                capacity: ColumnRef::Name("capacity".to_string()),
+                state: Some(ColumnRef::Name("state".to_string())),
+            },
+            timestamp: TimestampFormat::Epoch(EpochUnit::Seconds),
+            local_time: false,
+            capacity_unit: CapacityUnit::MilliwattHour,
+            states: HashMap::new(),
+        }
+    }
+
+    /// The csv with headers Date, Energy[J], Battery State
+    pub fn energy_log() -> Self {
+        CsvSchema {
+            delimiter: default_delimiter(),
+            has_headers: default_has_headers(),
+            columns: CsvColumns {
+                date_time: ColumnRef::Name("Date".to_string()),
+                capacity: ColumnRef::Name("Energy[J]".to_string()),
+                state: Some(ColumnRef::Name("Battery State".to_string())),
+            },
+            timestamp: TimestampFormat::Rfc3339,
+            local_time: false,
+            capacity_unit: CapacityUnit::Joule,
+            states: HashMap::from([
+                ("-1".to_string(), ChargeState::Discharging),
+                ("1".to_string(), ChargeState::Charging),
+            ]),
+        }
+    }
+
+    /// Reads the schema from a toml or json file, depending upon the extension.
+    pub fn from_file(path: &str) -> Result<Self, BatteryError> {
+        let contents = std::fs::read_to_string(path)?;
+
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&contents)?),
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            _ => Err(BatteryError::UnsupportedFormat(format!(
+                "Expected a .toml or .json schema, found {path}"
+            ))),
+        }
+    }
+
+    fn state(&self, value: &str) -> ChargeState {
+        let value = value.trim();
+
+        if !self.states.is_empty() {
+            return self
+                .states
+                .get(value)
+                .copied()
+                .unwrap_or(ChargeState::Unknown);
+        }
+
+        match value.to_lowercase().as_str() {
+            "charging" => ChargeState::Charging,
+            "discharging" => ChargeState::Discharging,
+            "fullycharged" => ChargeState::FullyCharged,
+            "pendingcharge" => ChargeState::PendingCharge,
+            _ => ChargeState::Unknown,
+        }
+    }
+
+    fn date_time(&self, value: &str) -> Option<DateTime<Utc>> {
+        let value = value.trim();
+
+        match &self.timestamp {
+            TimestampFormat::Epoch(unit) => {
+                let value: i64 = value.parse().ok()?;
+                let (seconds, nanoseconds) = match unit {
+                    EpochUnit::Seconds => (value, 0),
+                    EpochUnit::Milliseconds => {
+                        (value.div_euclid(1_000), value.rem_euclid(1_000) * 1_000_000)
+                    }
+                    EpochUnit::Microseconds => (
+                        value.div_euclid(1_000_000),
+                        value.rem_euclid(1_000_000) * 1_000,
+                    ),
+                    EpochUnit::Nanoseconds => (
+                        value.div_euclid(1_000_000_000),
+                        value.rem_euclid(1_000_000_000),
+                    ),
+                };
+                DateTime::<Utc>::from_timestamp(seconds, nanoseconds as u32)
+            }
+            // also accepts the other forms read by the deserializer of [DateTime<Utc>]
+            TimestampFormat::Rfc3339 => value.parse::<DateTime<Utc>>().ok(),
+            TimestampFormat::Format(format) => {
+                if let Ok(date_time) = DateTime::parse_from_str(value, format) {
+                    return Some(date_time.with_timezone(&Utc));
+                }
+
+                let date_time = NaiveDateTime::parse_from_str(value, format).ok()?;
+                if self.local_time {
+                    local_to_utc(date_time)
+                } else {
+                    Some(date_time.and_utc())
+                }
+            }
+        }
+    }
+}
+
+/// Reads the csv from given path as described by the schema.
+///
+/// # Parameters
+/// path: [&str] of the csv
+///
+/// schema: the [CsvSchema] describing the columns, delimiter and units of the csv
+///
+/// # Returns
+/// The [BatteryTimeSeries] of [BatteryHistoryRecord]s ordered by their date_time,
+/// or the error pointing to the row and column which couldn't be read.
+pub fn get_data_with_schema(
+    path: &str,
+    schema: &CsvSchema,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    parse_data_with_schema(&std::fs::read_to_string(path)?, schema)
+}
+
+/// Same as [get_data_with_schema] but reads the csv from a string.
+pub fn parse_data_with_schema(
+    contents: &str,
+    schema: &CsvSchema,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    if !schema.delimiter.is_ascii() {
+        return Err(BatteryError::InvalidOption(format!(
+            "The delimiter must be ascii, found {}",
+            schema.delimiter
+        )));
+    }
+
+    let mut series = BatteryTimeSeries::new();
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(schema.has_headers)
+        .delimiter(schema.delimiter as u8)
+        .from_reader(contents.as_bytes());
+
+    let headers = if schema.has_headers {
+        Some(rdr.headers()?.clone())
+    } else {
+        None
+    };
+    let date_time_index = column_index(&schema.columns.date_time, headers.as_ref())?;
+    let capacity_index = column_index(&schema.columns.capacity, headers.as_ref())?;
+    let state_index = match &schema.columns.state {
+        Some(column) => Some(column_index(column, headers.as_ref())?),
+        None => None,
+    };
+
+    for result in rdr.records() {
+        let record = result?;
+
+        // the error at given column of the row
+        let invalid = |index: usize, message: String| BatteryError::Csv {
+            line: record.position().map(|position| position.line()),
+            column: Some(
+                headers
+                    .as_ref()
+                    .and_then(|headers| headers.get(index))
+                    .map(|header| header.trim().to_string())
+                    .unwrap_or((index + 1).to_string()),
+            ),
+            message,
+        };
+        let field = |index: usize| {
+            record
+                .get(index)
+                .ok_or_else(|| invalid(index, "Missing column".to_string()))
+        };
+
+        let date_time_value = field(date_time_index)?;
+        let date_time = schema.date_time(date_time_value).ok_or_else(|| {
+            invalid(
+                date_time_index,
+                format!("Invalid timestamp {date_time_value}"),
+            )
+        })?;
+
+        let capacity_value = field(capacity_index)?;
+        let capacity: f64 = capacity_value
+            .trim()
+            .parse()
+            .map_err(|_| invalid(capacity_index, format!("Invalid capacity {capacity_value}")))?;
+
+        let state = match state_index {
+            Some(index) => schema.state(field(index)?),
+            None => ChargeState::Unknown,
+        };
+
+        series.insert(BatteryHistoryRecord {
+            date_time,
+            capacity: Capacity::new(capacity, schema.capacity_unit),
+            state,
+        });
+    }
+
+    Ok(series)
+}
+
+fn column_index(column: &ColumnRef, headers: Option<&StringRecord>) -> Result<usize, BatteryError> {
+    match (column, headers) {
+        (ColumnRef::Index(index), _) => Ok(*index),
+        (ColumnRef::Name(name), Some(headers)) => headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| BatteryError::Csv {
+                line: Some(1),
+                column: Some(name.clone()),
+                message: "Couldn't find the column in the headers".to_string(),
+            }),
+        (ColumnRef::Name(name), None) => Err(BatteryError::InvalidOption(format!(
+            "The column {name} can't be referred by name without headers"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn schema(timestamp: TimestampFormat) -> CsvSchema {
+        CsvSchema {
+            timestamp,
+            ..CsvSchema::history()
+        }
+    }
+
+    fn first_date_time(contents: &str, schema: &CsvSchema) -> DateTime<Utc> {
+        parse_data_with_schema(contents, schema)
+            .unwrap()
+            .first()
+            .unwrap()
+            .date_time
+    }
+
+    #[test]
+    fn named_columns_in_any_order() {
+        let contents =
+            "state,capacity,date_time\nCharging,1000,1697788800\nfullycharged,2000,1697788860\n";
+
+        let series = parse_data_with_schema(contents, &CsvSchema::history()).unwrap();
+        let records = series.iter().collect::<Vec<_>>();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].date_time, date_time("2023-10-20T08:00:00Z"));
+        assert_eq!(records[0].capacity, Capacity::milliwatt_hours(1000.0));
+        assert_eq!(records[0].state, ChargeState::Charging);
+        assert_eq!(records[1].state, ChargeState::FullyCharged);
+    }
+
+    #[test]
+    fn indexed_columns_without_headers() {
+        let schema = CsvSchema {
+            delimiter: ';',
+            has_headers: false,
+            columns: CsvColumns {
+                date_time: ColumnRef::Index(2),
+                capacity: ColumnRef::Index(0),
+                state: None,
+            },
+            capacity_unit: CapacityUnit::Percent,
+            ..CsvSchema::history()
+        };
+
+        let series = parse_data_with_schema("55;ignored;1697788800\n", &schema).unwrap();
+        let record = series.first().unwrap();
+
+        assert_eq!(record.date_time, date_time("2023-10-20T08:00:00Z"));
+        assert_eq!(record.capacity, Capacity::percent(55.0));
+        assert_eq!(record.state, ChargeState::Unknown);
+    }
+
+    #[test]
+    fn epoch_units() {
+        let expected = date_time("2023-10-20T08:00:00.123Z");
+
+        for (unit, value) in [
+            (EpochUnit::Milliseconds, "1697788800123"),
+            (EpochUnit::Microseconds, "1697788800123000"),
+            (EpochUnit::Nanoseconds, "1697788800123000000"),
+        ] {
+            let contents = format!("date_time,capacity,state\n{value},1,\n");
+            assert_eq!(
+                first_date_time(&contents, &schema(TimestampFormat::Epoch(unit))),
+                expected
+            );
+        }
+
+        assert_eq!(
+            first_date_time(
+                "date_time,capacity,state\n1697788800,1,\n",
+                &schema(TimestampFormat::Epoch(EpochUnit::Seconds))
+            ),
+            date_time("2023-10-20T08:00:00Z")
+        );
+    }
+
+    #[test]
+    fn rfc3339_and_strftime_timestamps() {
+        assert_eq!(
+            first_date_time(
+                "date_time,capacity,state\n2023-10-20T10:00:00+02:00,1,\n",
+                &schema(TimestampFormat::Rfc3339)
+            ),
+            date_time("2023-10-20T08:00:00Z")
+        );
+
+        // with an offset
+        assert_eq!(
+            first_date_time(
+                "date_time,capacity,state\n20/10/2023 10:00 +0200,1,\n",
+                &schema(TimestampFormat::Format("%d/%m/%Y %H:%M %z".to_string()))
+            ),
+            date_time("2023-10-20T08:00:00Z")
+        );
+
+        // without an offset the time is in UTC, or in the local time when asked for
+        let naive = schema(TimestampFormat::Format("%d/%m/%Y %H:%M".to_string()));
+        let contents = "date_time,capacity,state\n20/10/2023 08:00,1,\n";
+        assert_eq!(
+            first_date_time(contents, &naive),
+            date_time("2023-10-20T08:00:00Z")
+        );
+        assert_eq!(
+            first_date_time(
+                contents,
+                &CsvSchema {
+                    local_time: true,
+                    ..naive
+                }
+            ),
+            local_to_utc(
+                NaiveDateTime::parse_from_str("2023-10-20 08:00", "%Y-%m-%d %H:%M").unwrap()
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn state_mapping() {
+        let contents = "Date,Energy[J],Battery State\n\
+                        2023-10-20T08:00:00Z,3600,1\n\
+                        2023-10-20T08:01:00Z,3500,-1\n\
+                        2023-10-20T08:02:00Z,3500,0\n";
+
+        let series = parse_data_with_schema(contents, &CsvSchema::energy_log()).unwrap();
+        let states = series.iter().map(|record| record.state).collect::<Vec<_>>();
+
+        assert_eq!(
+            states,
+            [
+                ChargeState::Charging,
+                ChargeState::Discharging,
+                ChargeState::Unknown
+            ]
+        );
+        assert_eq!(
+            series.first().unwrap().capacity,
+            Capacity::milliwatt_hours(1000.0)
+        );
+    }
+
+    #[test]
+    fn invalid_capacity() {
+        let contents = "date_time,capacity,state\n1697788800,1000,\n1697788860,full,\n";
+
+        match parse_data_with_schema(contents, &CsvSchema::history()) {
+            Err(BatteryError::Csv {
+                line,
+                column,
+                message,
+            }) => {
+                assert_eq!(line, Some(3));
+                assert_eq!(column.as_deref(), Some("capacity"));
+                assert_eq!(message, "Invalid capacity full");
+            }
+            result => panic!("Expected an invalid capacity, found {result:?}"),
+        }
+    }
+
+    #[test]
+    fn missing_columns() {
+        // not in the headers
+        match parse_data_with_schema("date_time,energy\n1697788800,1000\n", &CsvSchema::history()) {
+            Err(BatteryError::Csv { line, column, .. }) => {
+                assert_eq!(line, Some(1));
+                assert_eq!(column.as_deref(), Some("capacity"));
+            }
+            result => panic!("Expected a missing column, found {result:?}"),
+        }
+
+        // past the end of the row
+        let schema = CsvSchema {
+            has_headers: false,
+            columns: CsvColumns {
+                date_time: ColumnRef::Index(0),
+                capacity: ColumnRef::Index(3),
+                state: None,
+            },
+            ..CsvSchema::history()
+        };
+        match parse_data_with_schema("1697788800,1000\n", &schema) {
+            Err(BatteryError::Csv {
+                line,
+                column,
+                message,
+            }) => {
+                assert_eq!(line, Some(1));
+                assert_eq!(column.as_deref(), Some("4"));
+                assert_eq!(message, "Missing column");
+            }
+            result => panic!("Expected a missing column, found {result:?}"),
+        }
+
+        // names need the headers
+        let schema = CsvSchema {
+            has_headers: false,
+            ..CsvSchema::history()
+        };
+        assert!(matches!(
+            parse_data_with_schema("1697788800,1000,\n", &schema),
+            Err(BatteryError::InvalidOption(_))
+        ));
+    }
+}
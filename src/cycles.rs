@@ -0,0 +1,337 @@
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::error::BatteryError;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+use crate::units::CapacityUnit;
+
+/// Full or half cycle found by the rainflow counting.
+#[derive(Debug, Clone, Copy)]
+pub struct Cycle {
+    /// depth of discharge in percentage of the full capacity
+    pub depth: f64,
+    /// 1.0 for a full cycle and 0.5 for a half cycle
+    pub count: f64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Number of cycles with the depth of discharge in `from..to` percentage.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthBin {
+    pub from: f64,
+    pub to: f64,
+    pub cycles: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CycleSummary {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// the capacity which makes up one full cycle
+    pub full_capacity: Capacity,
+    /// sum of all the decreases of the capacity, in the unit of the full capacity
+    pub discharged: Capacity,
+    /// discharged capacity divided by the full capacity
+    pub equivalent_full_cycles: f64,
+    /// equivalent full cycles per day over the whole history, zero when it doesn't span any time
+    pub cycles_per_day: f64,
+    /// the equivalent full cycles counted up to each record
+    pub cumulative: Vec<(DateTime<Utc>, f64)>,
+    /// the partial cycles found by rainflow counting
+    pub cycles: Vec<Cycle>,
+}
+
+impl CycleSummary {
+    /// Sum of the counts of the rainflow cycles
+    pub fn rainflow_cycles(&self) -> f64 {
+        self.cycles.iter().map(|cycle| cycle.count).sum()
+    }
+
+    /// Breaks the rainflow cycles down by their depth of discharge, in bins of given width in percentage
+    /// e.g. 10.0 gives the bins 0-10%, 10-20%, ..., 90-100%. The full cycles of 100% are in the last bin.
+    pub fn depth_breakdown(&self, bin_width: f64) -> Vec<DepthBin> {
+        if bin_width <= 0.0 {
+            return Vec::new();
+        }
+
+        let max_depth = self
+            .cycles
+            .iter()
+            .map(|cycle| cycle.depth)
+            .fold(100.0, f64::max);
+        let number_of_bins = (max_depth / bin_width).ceil().max(1.0) as usize;
+
+        let mut bins: Vec<DepthBin> = (0..number_of_bins)
+            .map(|i| DepthBin {
+                from: i as f64 * bin_width,
+                to: (i + 1) as f64 * bin_width,
+                cycles: 0.0,
+            })
+            .collect();
+
+        for cycle in self.cycles.iter() {
+            let index = ((cycle.depth / bin_width) as usize).min(number_of_bins - 1);
+            bins[index].cycles += cycle.count;
+        }
+
+        bins
+    }
+}
+
+/// Counts the charge cycles of the battery history.
+///
+/// # Parameters
+/// data: the history, e.g. returned by [crate::read_data::get_data] or [crate::read_data::get_log]
+///
+/// full_capacity: the capacity of a full charge, when [None] it is 100% for the histories in percentage
+/// and the highest capacity of the history otherwise
+///
+/// # Returns
+/// The [CycleSummary] with the equivalent full cycles and the rainflow cycles, or the error when
+/// the history is empty or its capacities can't be converted into the unit of the full capacity.
+pub fn count_cycles(
+    data: &BatteryTimeSeries,
+    full_capacity: Option<Capacity>,
+) -> Result<CycleSummary, BatteryError> {
+    let first = data.first().ok_or(BatteryError::EmptyData)?;
+    let last = data.last().unwrap();
+
+    let full_capacity = match full_capacity {
+        Some(full_capacity) => full_capacity,
+        None if first.capacity.unit() == CapacityUnit::Percent => Capacity::percent(100.0),
+        None => data
+            .iter()
+            .map(|record| record.capacity)
+            .reduce(|max, capacity| if capacity > max { capacity } else { max })
+            .unwrap(),
+    };
+    if full_capacity.value() <= 0.0 {
+        return Err(BatteryError::InvalidOption(format!(
+            "The full capacity must be positive, found {full_capacity}"
+        )));
+    }
+
+    // the capacities in percentage of the full capacity
+    let mut levels: Vec<(DateTime<Utc>, f64)> = Vec::with_capacity(data.len());
+    for record in data.iter() {
+        let capacity =
+            record
+                .capacity
+                .to(full_capacity.unit())
+                .ok_or(BatteryError::IncompatibleUnits {
+                    from: record.capacity.unit(),
+                    to: full_capacity.unit(),
+                })?;
+        levels.push((
+            record.date_time,
+            capacity.value() * 100.0 / full_capacity.value(),
+        ));
+    }
+
+    let mut discharged = 0.0;
+    let mut cumulative = Vec::with_capacity(levels.len());
+    cumulative.push((levels[0].0, 0.0));
+    for pair in levels.windows(2) {
+        discharged += (pair[0].1 - pair[1].1).max(0.0);
+        cumulative.push((pair[1].0, discharged / 100.0));
+    }
+
+    let equivalent_full_cycles = discharged / 100.0;
+    let days = (last.date_time - first.date_time).num_seconds() as f64 / 86400.0;
+
+    Ok(CycleSummary {
+        start: first.date_time,
+        end: last.date_time,
+        full_capacity,
+        discharged: Capacity::new(
+            discharged * full_capacity.value() / 100.0,
+            full_capacity.unit(),
+        ),
+        equivalent_full_cycles,
+        cycles_per_day: if days > 0.0 {
+            equivalent_full_cycles / days
+        } else {
+            0.0
+        },
+        cumulative,
+        cycles: rainflow(&reversals(&levels)),
+    })
+}
+
+/// The first and the last points along with the points where the trend changes
+fn reversals(levels: &[(DateTime<Utc>, f64)]) -> Vec<(DateTime<Utc>, f64)> {
+    let mut reversals: Vec<(DateTime<Utc>, f64)> = Vec::new();
+
+    for point in levels.iter() {
+        match reversals.len() {
+            0 => reversals.push(*point),
+            1 => {
+                if point.1 != reversals[0].1 {
+                    reversals.push(*point);
+                }
+            }
+            n => {
+                let (before, last) = (reversals[n - 2].1, reversals[n - 1].1);
+                if point.1 == last {
+                    continue;
+                }
+
+                // still going in the same direction, so the last point isn't a reversal
+                if (last - before).signum() == (point.1 - last).signum() {
+                    reversals[n - 1] = *point;
+                } else {
+                    reversals.push(*point);
+                }
+            }
+        }
+    }
+
+    reversals
+}
+
+/// Three point rainflow counting as described in ASTM E1049
+fn rainflow(reversals: &[(DateTime<Utc>, f64)]) -> Vec<Cycle> {
+    let mut cycles = Vec::new();
+    let mut stack: Vec<(DateTime<Utc>, f64)> = Vec::new();
+
+    let cycle = |from: (DateTime<Utc>, f64), to: (DateTime<Utc>, f64), count: f64| Cycle {
+        depth: (to.1 - from.1).abs(),
+        count,
+        start: from.0,
+        end: to.0,
+    };
+
+    for point in reversals.iter() {
+        stack.push(*point);
+
+        while stack.len() >= 3 {
+            let n = stack.len();
+            let x = (stack[n - 1].1 - stack[n - 2].1).abs();
+            let y = (stack[n - 2].1 - stack[n - 3].1).abs();
+
+            if x < y {
+                break;
+            }
+
+            if n == 3 {
+                // the range containing the starting point is only counted as a half cycle
+                cycles.push(cycle(stack[0], stack[1], 0.5));
+                stack.remove(0);
+            } else {
+                cycles.push(cycle(stack[n - 3], stack[n - 2], 1.0));
+                stack.drain(n - 3..n - 1);
+            }
+        }
+    }
+
+    // the remaining ranges are half cycles
+    for pair in stack.windows(2) {
+        cycles.push(cycle(pair[0], pair[1], 0.5));
+    }
+
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_data::ChargeState;
+    use crate::test_util::{self, hour, per_hour};
+    use chrono::Duration;
+
+    fn series(percentages: &[f64]) -> BatteryTimeSeries {
+        test_util::series(
+            Duration::hours(1),
+            percentages
+                .iter()
+                .map(|percentage| (Capacity::percent(*percentage), ChargeState::Unknown)),
+        )
+    }
+
+    /// The counts of each depth, in ascending order of the depth
+    fn counts_by_depth(cycles: &[Cycle]) -> Vec<(f64, f64)> {
+        let mut counts: Vec<(f64, f64)> = Vec::new();
+        for cycle in cycles.iter() {
+            match counts.iter_mut().find(|(depth, _)| *depth == cycle.depth) {
+                Some((_, count)) => *count += cycle.count,
+                None => counts.push((cycle.depth, cycle.count)),
+            }
+        }
+        counts.sort_by(|a, b| a.0.total_cmp(&b.0));
+        counts
+    }
+
+    #[test]
+    fn astm_e1049_rainflow() {
+        // the example of ASTM E1049 -2, 1, -3, 5, -1, 3, -4, 4, -2 as 50 + 10 * x percent
+        let summary = count_cycles(
+            &series(&[30.0, 60.0, 20.0, 100.0, 40.0, 80.0, 10.0, 90.0, 30.0]),
+            None,
+        )
+        .unwrap();
+
+        // the ranges 3, 4, 6, 8 and 9 are counted 0.5, 1.5, 0.5, 1 and 0.5 times
+        assert_eq!(
+            counts_by_depth(&summary.cycles),
+            [
+                (30.0, 0.5),
+                (40.0, 1.5),
+                (60.0, 0.5),
+                (80.0, 1.0),
+                (90.0, 0.5)
+            ]
+        );
+        assert_eq!(summary.rainflow_cycles(), 4.0);
+
+        // the full cycle of range 4 is from -1 to 3
+        let full = summary
+            .cycles
+            .iter()
+            .find(|cycle| cycle.count == 1.0 && cycle.depth == 40.0)
+            .unwrap();
+        assert_eq!((full.start, full.end), (hour(4), hour(5)));
+    }
+
+    #[test]
+    fn equivalent_full_cycles() {
+        let summary = count_cycles(
+            &series(&[30.0, 60.0, 20.0, 100.0, 40.0, 80.0, 10.0, 90.0, 30.0]),
+            None,
+        )
+        .unwrap();
+
+        // 40 + 60 + 70 + 60 percentage points discharged
+        assert!((summary.equivalent_full_cycles - 2.3).abs() < 1e-9);
+        assert!((summary.cycles_per_day - 2.3 * 3.0).abs() < 1e-9);
+        assert_eq!(summary.cumulative.len(), 9);
+        assert_eq!(summary.discharged, Capacity::percent(230.0));
+
+        let bins = summary.depth_breakdown(50.0);
+        assert_eq!(bins.len(), 2);
+        assert_eq!(bins[0].cycles, 2.0);
+        assert_eq!(bins[1].cycles, 2.0);
+    }
+
+    #[test]
+    fn full_capacity_in_another_unit() {
+        let data = per_hour(&[
+            (100.0, ChargeState::Unknown),
+            (50.0, ChargeState::Unknown),
+            (100.0, ChargeState::Unknown),
+        ]);
+
+        let summary = count_cycles(&data, Some(Capacity::watt_hours(0.2))).unwrap();
+        assert_eq!(counts_by_depth(&summary.cycles), [(25.0, 1.0)]);
+
+        assert!(matches!(
+            count_cycles(&data, Some(Capacity::percent(100.0))),
+            Err(BatteryError::IncompatibleUnits { .. })
+        ));
+        assert!(matches!(
+            count_cycles(&BatteryTimeSeries::new(), None),
+            Err(BatteryError::EmptyData)
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::DateTime;
+use chrono::Utc;
+use plotters::drawing::DrawingAreaErrorKind;
+use std::fmt;
+use std::io;
+
+use crate::units::CapacityUnit;
+
+/// Errors of reading, analysing and plotting the battery data.
+#[derive(Debug)]
+pub enum BatteryError {
+    /// reading or writing a file failed
+    Io(io::Error),
+    /// a row of a csv file couldn't be read
+    Csv {
+        /// line of the row in the file, starting at 1
+        line: Option<u64>,
+        /// the column starting at 1, or its name in the headers
+        column: Option<String>,
+        message: String,
+    },
+    /// a file other than csv couldn't be parsed, e.g. a battery report, a log or a schema
+    Parse {
+        /// line in the file, starting at 1
+        line: Option<u64>,
+        message: String,
+    },
+    /// the format of the file isn't recognized
+    UnsupportedFormat(String),
+    /// there is no data to work with
+    EmptyData,
+    /// there is no data left between the dates, e.g. after applying the date window of a plot
+    EmptyDateRange {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    /// capacities which can't be converted into each other, e.g. a percentage and an energy
+    IncompatibleUnits { from: CapacityUnit, to: CapacityUnit },
+    /// an option or an argument is out of its range
+    InvalidOption(String),
+    /// the prediction couldn't be made, e.g. there is no rate to extend
+    Prediction(String),
+    /// drawing the plot failed
+    Rendering(String),
+}
+
+impl fmt::Display for BatteryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatteryError::Io(error) => write!(f, "{error}"),
+            BatteryError::Csv {
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "Invalid csv")?;
+                if let Some(line) = line {
+                    write!(f, " in line {line}")?;
+                }
+                if let Some(column) = column {
+                    write!(f, ", column {column}")?;
+                }
+                write!(f, ": {message}")
+            }
+            BatteryError::Parse { line, message } => match line {
+                Some(line) => write!(f, "Couldn't parse line {line}: {message}"),
+                None => write!(f, "Couldn't parse: {message}"),
+            },
+            BatteryError::UnsupportedFormat(message) => write!(f, "{message}"),
+            BatteryError::EmptyData => write!(f, "The provided data is empty."),
+            BatteryError::EmptyDateRange { start, end } => {
+                write!(f, "There is no data between {start} and {end}")
+            }
+            BatteryError::IncompatibleUnits { from, to } => {
+                write!(f, "Can't convert the capacities in {from} into {to}")
+            }
+            BatteryError::InvalidOption(message) => write!(f, "{message}"),
+            BatteryError::Prediction(message) => write!(f, "{message}"),
+            BatteryError::Rendering(message) => write!(f, "Couldn't draw the plot: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for BatteryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatteryError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BatteryError {
+    fn from(error: io::Error) -> Self {
+        BatteryError::Io(error)
+    }
+}
+
+impl From<csv::Error> for BatteryError {
+    fn from(error: csv::Error) -> Self {
+        let line = error.position().map(|position| position.line());
+        let message = error.to_string();
+
+        match error.into_kind() {
+            csv::ErrorKind::Io(error) => BatteryError::Io(error),
+            csv::ErrorKind::Deserialize { err, .. } => BatteryError::Csv {
+                line,
+                column: err.field().map(|field| (field + 1).to_string()),
+                message: err.kind().to_string(),
+            },
+            _ => BatteryError::Csv {
+                line,
+                column: None,
+                message,
+            },
+        }
+    }
+}
+
+impl From<serde_json::Error> for BatteryError {
+    fn from(error: serde_json::Error) -> Self {
+        if error.is_io() {
+            return BatteryError::Io(error.into());
+        }
+
+        BatteryError::Parse {
+            line: Some(error.line() as u64).filter(|line| *line > 0),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<toml::de::Error> for BatteryError {
+    fn from(error: toml::de::Error) -> Self {
+        BatteryError::Parse {
+            line: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<roxmltree::Error> for BatteryError {
+    fn from(error: roxmltree::Error) -> Self {
+        BatteryError::Parse {
+            line: Some(error.pos().row as u64),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl<E: std::error::Error + Send + Sync> From<DrawingAreaErrorKind<E>> for BatteryError {
+    fn from(error: DrawingAreaErrorKind<E>) -> Self {
+        BatteryError::Rendering(error.to_string())
+    }
+}
@@ -0,0 +1,301 @@
+use chrono::serde::ts_seconds;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use csv::ReaderBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error::BatteryError;
+use crate::read_html::CapacityHistoryRecord;
+use crate::units::as_milliwatt_hours;
+use crate::units::Capacity;
+
+/// State of health in percentage below which the battery is usually considered for replacement.
+pub const REPLACEMENT_THRESHOLD: f64 = 80.0;
+
+/// Full charge capacity of the battery at some time along with its design capacity.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthRecord {
+    #[serde(with = "ts_seconds")]
+    pub date_time: DateTime<Utc>,
+    #[serde(with = "as_milliwatt_hours")]
+    pub full_charge_capacity: Capacity,
+    #[serde(with = "as_milliwatt_hours")]
+    pub design_capacity: Capacity,
+}
+
+impl HealthRecord {
+    /// Full charge capacity as the percentage of the design capacity,
+    /// [None] when they can't be converted into the same unit or the design capacity is zero.
+    pub fn state_of_health(&self) -> Option<f64> {
+        let full_charge_capacity = self.full_charge_capacity.to(self.design_capacity.unit())?;
+        if self.design_capacity.value() == 0.0 {
+            return None;
+        }
+
+        Some(full_charge_capacity.value() * 100.0 / self.design_capacity.value())
+    }
+}
+
+/// The capacity history of the battery report is measured over a period, the records are at the end of it.
+impl From<&CapacityHistoryRecord> for HealthRecord {
+    fn from(record: &CapacityHistoryRecord) -> Self {
+        HealthRecord {
+            date_time: record
+                .period
+                .end
+                .and_hms_opt(0, 0, 0)
+                .expect("midnight is a valid time")
+                .and_utc(),
+            full_charge_capacity: record.full_charge_capacity,
+            design_capacity: record.design_capacity,
+        }
+    }
+}
+
+/// Linear fit of the state of health over time.
+#[derive(Debug, Clone, Copy)]
+pub struct WearTrend {
+    /// time of the first record used for the fit
+    pub reference: DateTime<Utc>,
+    /// fitted state of health in percentage at the reference time
+    pub state_of_health: f64,
+    /// change of the state of health in percentage points per day, negative while wearing
+    pub slope: f64,
+}
+
+impl WearTrend {
+    pub fn state_of_health_at(&self, date_time: DateTime<Utc>) -> f64 {
+        self.state_of_health + self.slope * days_between(self.reference, date_time)
+    }
+
+    /// Loss of the state of health in percentage points per year
+    pub fn wear_per_year(&self) -> f64 {
+        -self.slope * 365.25
+    }
+
+    /// When the fitted state of health reaches given percentage, [None] when the battery isn't wearing.
+    pub fn date_reaching(&self, state_of_health: f64) -> Option<DateTime<Utc>> {
+        if self.slope >= 0.0 {
+            return None;
+        }
+
+        let days = (state_of_health - self.state_of_health) / self.slope;
+        self.reference
+            .checked_add_signed(Duration::seconds((days * 86400.0) as i64))
+    }
+
+    /// When the fitted state of health reaches [REPLACEMENT_THRESHOLD]
+    pub fn replacement_date(&self) -> Option<DateTime<Utc>> {
+        self.date_reaching(REPLACEMENT_THRESHOLD)
+    }
+}
+
+/// Least squares fit of the state of health of the records over time.
+///
+/// # Returns
+/// The [WearTrend], or [None] when there aren't at least two records at different times
+/// with known state of health.
+pub fn fit_wear_trend(records: &[HealthRecord]) -> Option<WearTrend> {
+    let points: Vec<(DateTime<Utc>, f64)> = records
+        .iter()
+        .filter_map(|record| Some((record.date_time, record.state_of_health()?)))
+        .collect();
+
+    let reference = points.iter().map(|(date_time, _)| *date_time).min()?;
+
+    let count = points.len() as f64;
+    let mean_x = points
+        .iter()
+        .map(|(date_time, _)| days_between(reference, *date_time))
+        .sum::<f64>()
+        / count;
+    let mean_y = points.iter().map(|(_, soh)| soh).sum::<f64>() / count;
+
+    let mut covariance = 0.0;
+    let mut variance = 0.0;
+    for (date_time, soh) in points.iter() {
+        let x = days_between(reference, *date_time) - mean_x;
+        covariance += x * (soh - mean_y);
+        variance += x * x;
+    }
+
+    if variance == 0.0 {
+        return None;
+    }
+
+    let slope = covariance / variance;
+    Some(WearTrend {
+        reference,
+        state_of_health: mean_y - slope * mean_x,
+        slope,
+    })
+}
+
+/// The health history of the capacity history of a battery report, sorted by time.
+pub fn health_from_capacity_history(history: &[CapacityHistoryRecord]) -> Vec<HealthRecord> {
+    let mut records: Vec<HealthRecord> = history.iter().map(HealthRecord::from).collect();
+    records.sort_by_key(|record| record.date_time);
+    records
+}
+
+/// Reads the csv with headers date_time, full_charge_capacity, design_capacity,
+/// e.g. written by [crate::read_sysfs::SysfsSampler::append_health], sorted by time.
+pub fn get_health_data(path: &str) -> Result<Vec<HealthRecord>, BatteryError> {
+    let mut records = Vec::new();
+
+    let mut rdr = ReaderBuilder::new().has_headers(true).from_path(path)?;
+    for result in rdr.deserialize::<HealthRecord>() {
+        records.push(result?);
+    }
+
+    records.sort_by_key(|record| record.date_time);
+    Ok(records)
+}
+
+fn days_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_seconds() as f64 / 86400.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_html::ReportPeriod;
+    use crate::read_sysfs::SysfsSampler;
+    use crate::test_util::{hour, TempDir};
+    use chrono::NaiveDate;
+
+    fn day(days: i64) -> DateTime<Utc> {
+        hour(24 * days)
+    }
+
+    fn record(days: i64, state_of_health: f64) -> HealthRecord {
+        HealthRecord {
+            date_time: day(days),
+            full_charge_capacity: Capacity::milliwatt_hours(500.0 * state_of_health),
+            design_capacity: Capacity::milliwatt_hours(50000.0),
+        }
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-6,
+            "Expected {expected}, found {value}"
+        );
+    }
+
+    #[test]
+    fn linear_fade() {
+        // 0.01 percentage points per day from 100 %, given out of order
+        let mut records: Vec<HealthRecord> = (0..=12)
+            .map(|month| record(month * 30, 100.0 - 0.3 * month as f64))
+            .collect();
+        records.reverse();
+
+        let trend = fit_wear_trend(&records).unwrap();
+
+        assert_eq!(trend.reference, day(0));
+        assert_close(trend.state_of_health, 100.0);
+        assert_close(trend.slope, -0.01);
+        assert_close(trend.wear_per_year(), 3.6525);
+        assert_close(trend.state_of_health_at(day(1000)), 90.0);
+        assert_eq!(trend.replacement_date(), Some(day(2000)));
+        assert_eq!(trend.date_reaching(95.0), Some(day(500)));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let dir = TempDir::new("health-csv");
+        let sampler = SysfsSampler::new(dir.path().join("sysfs"));
+        let csv = dir.path().join("health.csv");
+        dir.write("sysfs/BAT0/energy_full_design", "60000000\n");
+
+        // appended out of order
+        dir.write("sysfs/BAT0/energy_full", "48000000\n");
+        sampler.append_health(&csv, day(30)).unwrap();
+        dir.write("sysfs/BAT0/energy_full", "54000000\n");
+        sampler.append_health(&csv, day(0)).unwrap();
+
+        let records = get_health_data(&csv.to_string_lossy()).unwrap();
+        let records: Vec<(DateTime<Utc>, Capacity, Capacity)> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.date_time,
+                    record.full_charge_capacity,
+                    record.design_capacity,
+                )
+            })
+            .collect();
+        assert_eq!(
+            records,
+            [
+                (
+                    day(0),
+                    Capacity::milliwatt_hours(54000.0),
+                    Capacity::milliwatt_hours(60000.0)
+                ),
+                (
+                    day(30),
+                    Capacity::milliwatt_hours(48000.0),
+                    Capacity::milliwatt_hours(60000.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn capacity_history_at_the_end_of_the_period() {
+        let date = |day| NaiveDate::from_ymd_opt(2023, 10, day).unwrap();
+        let history = [
+            CapacityHistoryRecord {
+                period: ReportPeriod {
+                    start: date(8),
+                    end: date(15),
+                },
+                full_charge_capacity: Capacity::milliwatt_hours(45000.0),
+                design_capacity: Capacity::milliwatt_hours(50000.0),
+            },
+            CapacityHistoryRecord {
+                period: ReportPeriod {
+                    start: date(1),
+                    end: date(8),
+                },
+                full_charge_capacity: Capacity::milliwatt_hours(46000.0),
+                design_capacity: Capacity::milliwatt_hours(50000.0),
+            },
+        ];
+
+        let records = health_from_capacity_history(&history);
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.date_time.to_rfc3339())
+                .collect::<Vec<String>>(),
+            ["2023-10-08T00:00:00+00:00", "2023-10-15T00:00:00+00:00"]
+        );
+        assert_close(records[0].state_of_health().unwrap(), 92.0);
+        assert_close(records[1].state_of_health().unwrap(), 90.0);
+    }
+
+    #[test]
+    fn no_trend() {
+        // not wearing
+        let trend = fit_wear_trend(&[record(0, 95.0), record(30, 95.0)]).unwrap();
+        assert_close(trend.slope, 0.0);
+        assert_eq!(trend.replacement_date(), None);
+
+        // all at the same time
+        assert!(fit_wear_trend(&[record(0, 95.0), record(0, 90.0)]).is_none());
+        assert!(fit_wear_trend(&[record(0, 95.0)]).is_none());
+        assert!(fit_wear_trend(&[]).is_none());
+
+        // the records without state of health are ignored
+        let unknown = HealthRecord {
+            design_capacity: Capacity::milliwatt_hours(0.0),
+            ..record(30, 90.0)
+        };
+        assert!(fit_wear_trend(&[record(0, 95.0), unknown]).is_none());
+    }
+}
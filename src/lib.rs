@@ -1,403 +1,320 @@
 use chrono::{DateTime, Duration, Utc};
 pub use read_data::BatteryHistoryRecord;
 pub use read_data::ChargeState;
-
+pub use backtest::{backtest, BacktestOptions, BacktestReport, BacktestResult};
+pub use csv_schema::{ColumnRef, CsvColumns, CsvSchema, EpochUnit, TimestampFormat};
+pub use clock::Clock;
+pub use cycles::{count_cycles, Cycle, CycleSummary, DepthBin};
+pub use error::BatteryError;
+pub use health::{
+    fit_wear_trend, health_from_capacity_history, HealthRecord, WearTrend, REPLACEMENT_THRESHOLD,
+};
+pub use load::{detect_format, load, load_as, InputFormat};
+pub use pipeline::{filter, load_all, plot, render, resample, PlotData, WindowBound};
+pub use plot::{PlotOptions, XAxisLabels};
+pub use predict::{predict, predict_with, Prediction, PredictionInterval, PredictionOptions};
+pub use predictor::{
+    HoltPredictor, LinearPredictor, NearestNeighbourPredictor, Predictor, ProfilePredictor,
+    SeasonalPredictor,
+};
+pub use read_android::{BatteryStatsEntry, PlugType};
+pub use read_html::{
+    BatteryReport, BatteryUsageRecord, CapacityHistoryRecord, LifeEstimateRecord, PowerSource,
+    PowerState, RecentUsageRecord, ReportPeriod, UsageHistoryRecord,
+};
+pub use read_macos::IoregBattery;
+pub use read_sysfs::{SysfsSample, SysfsSampler, DEFAULT_SYSFS_ROOT};
+pub use read_upower::{UpowerHistory, UpowerHistoryEntry, DEFAULT_UPOWER_DIR};
+pub use read_xml::{BatteryInfo, XmlBatteryReport};
+pub use session::{
+    find_state_disagreements, segment_sessions, segment_sessions_with, SegmentationOptions,
+    Session, SessionKind, StateDisagreement, TrendSource,
+};
+pub use time_series::BatteryTimeSeries;
+pub use units::{Capacity, CapacityUnit};
+
+mod backtest;
+mod clock;
+mod csv_schema;
+mod cycles;
+mod error;
+mod health;
+mod load;
+mod pipeline;
 mod plot;
+mod predict;
+mod predictor;
+mod read_android;
 mod read_data;
-
-use crate::plot::start_battery_plot;
+mod read_html;
+mod read_macos;
+mod read_sysfs;
+mod read_upower;
+mod read_xml;
+mod session;
+#[cfg(test)]
+mod test_util;
+mod time_series;
+mod units;
+
+use crate::csv_schema::get_data_with_schema;
+use crate::health::get_health_data;
+use crate::plot::plot_health;
+use crate::read_android::{get_batterystats_data, get_batterystats_history};
 use crate::read_data::{get_data, get_log};
-use crate::read_data::sort_hashmap;
+use crate::read_html::{get_html_data, get_html_report};
+use crate::read_macos::{get_ioreg_batteries, get_ioreg_data, get_pmset_data};
+use crate::read_upower::{get_upower_data, get_upower_history, get_upower_info};
+use crate::read_xml::{get_xml_data, get_xml_report};
 pub use plotters_cairo::CairoBackend;
 
 use plotters::prelude::*;
 use std::collections::HashMap;
-use std::error::Error;
-
-use makima_spline::Spline;
-
-enum PreviousVal {
-    None,
-    Increasing,
-    Decreasing,
-}
 
 pub fn display_error<'a, DB: DrawingBackend + 'a>(
     backend: DB,
     error_message: &str,
     pos: (i32, i32),
-) {
+) -> Result<(), BatteryError> {
     let drawing_area = backend.into_drawing_area();
-    drawing_area.fill(&BLACK).unwrap();
+    drawing_area.fill(&BLACK)?;
     let text_style = ("sans-serif", 20, &RED).into_text_style(&drawing_area);
     let errors = error_message.lines();
 
     for (i, error) in errors.enumerate() {
-        drawing_area
-            .draw_text(
-                error,
-                &text_style,
-                (
-                    pos.0,
-                    pos.1 + (i as f64 * text_style.font.get_size()) as i32,
-                ),
-            )
-            .unwrap();
+        drawing_area.draw_text(
+            error,
+            &text_style,
+            (
+                pos.0,
+                pos.1 + (i as f64 * text_style.font.get_size()) as i32,
+            ),
+        )?;
     }
+
+    Ok(())
 }
 
+/// Plots the data along with the predicted data, e.g. the `predicted_data` of [Prediction],
+/// as described by the options.
+///
+/// # Parameters
+/// backend: the backend for plotting e.g. CairoBackend, SVGBackend, etc
+///
+/// predicted_data: shown after the last record when [PlotOptions::show_prediction] is set
+/// and the window reaches the last record
+///
+/// data: the records to plot, in any unit which can be converted into the unit of the last record
+///
+/// options: the date window, interpolation, colours and the other [PlotOptions] of the graph
+///
+/// See [plot] for the stages of the graph.
 pub fn battery_plot_pdf<'a, DB: DrawingBackend + 'a>(
     backend: DB,
-    predicted_data: HashMap<DateTime<Utc>, BatteryHistoryRecord>,
-    data: HashMap<DateTime<Utc>, BatteryHistoryRecord>,
-    from_days_before: Option<i64>,
-    to_days_before: Option<i64>,
-    show_data_points: bool,
-    interpolate: bool,
-    show_prediction: bool,
-) -> Result<(), Box<dyn Error + 'a>> {
-    /* reading data from csv */
-
-    // debug
-    if data.is_empty() {
-        panic!("The provided data is empty.");
-    }
+    predicted_data: BatteryTimeSeries,
+    data: BatteryTimeSeries,
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    plot(
+        backend,
+        &data,
+        predicted_data,
+        (BatteryTimeSeries::new(), BatteryTimeSeries::new()),
+        options,
+    )
+}
 
-    // discard predicted data when show_prediction is false 
-    // and when the final date-time is past date-time
-    let all_data: HashMap<DateTime<Utc>, BatteryHistoryRecord> = match show_prediction {
-        true => match to_days_before {
-            Some(0) => {
-                HashMap::from_iter(data.into_iter().chain(predicted_data.clone().into_iter()))
-            }
-            _ => data,
-        },
-        false => data,
-    };
+/// Same as [battery_plot_pdf] with the prediction of [predict] or [predict_with], its [PredictionInterval]
+/// is shown as a translucent band around the predicted data.
+pub fn battery_plot_prediction_pdf<'a, DB: DrawingBackend + 'a>(
+    backend: DB,
+    prediction: Prediction,
+    data: BatteryTimeSeries,
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    plot(
+        backend,
+        &data,
+        prediction.predicted_data,
+        (prediction.interval.lower, prediction.interval.upper),
+        options,
+    )
+}
+
+/// Same as [battery_plot_prediction_pdf] with the prediction of any [Predictor], e.g. [HoltPredictor] or
+/// a custom one. The predictor is fitted on the whole data and forecasts up to the default maximum horizon
+/// of [PredictionOptions].
+pub fn battery_plot_predictor_pdf<'a, DB: DrawingBackend + 'a, P: Predictor + ?Sized>(
+    backend: DB,
+    predictor: &mut P,
+    data: BatteryTimeSeries,
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    let prediction = predictor
+        .fit(&data)
+        .and_then(|_| predictor.forecast(PredictionOptions::default().max_horizon))?;
+
+    battery_plot_prediction_pdf(backend, prediction, data, options)
+}
 
-    let end_date = all_data
+/// Plots the state of health of the records along with the fitted wear trend, the trend is extended
+/// up to the date it reaches [REPLACEMENT_THRESHOLD] but at most 5 years after the last record.
+pub fn battery_health_plot<'a, DB: DrawingBackend + 'a>(
+    backend: DB,
+    health: &[HealthRecord],
+) -> Result<(), BatteryError> {
+    let state_of_health: Vec<(DateTime<Utc>, f64)> = health
         .iter()
-        .reduce(|max_capacity_record, current| {
-            all_data
-                .get_key_value(max_capacity_record.0.max(current.0))
-                .unwrap()
-        })
-        .unwrap()
-        .0
-        .to_owned();
-
-    // all the data after the current date is prediction
-    let current_date_time = chrono::Utc::now();
-
-    let mut sanitized_data: HashMap<DateTime<Utc>, BatteryHistoryRecord> =
-        HashMap::from_iter(all_data);
-    let mut original_x_data: Vec<DateTime<Utc>> = Vec::new();
-    let mut original_y_data: Vec<i32> = Vec::new();
-
-    // removing all the entries before the start days
-    if let Some(number_of_days) = from_days_before {
-        let mut actual_number_of_days = number_of_days;
-
-        // if the prediction needs to be shown and to_days_before is zero
-        // i.e. showing only if the graph up to current is shown
-        if show_prediction && to_days_before.is_some() && to_days_before.unwrap() == 0 {
-            actual_number_of_days += 1;
-        }
+        .filter_map(|record| Some((record.date_time, record.state_of_health()?)))
+        .collect();
 
-        let start_date = end_date - chrono::Duration::days(actual_number_of_days);
-        sanitized_data.retain(|date, _| (date) > (&start_date));
-    }
+    let last_date = state_of_health
+        .iter()
+        .map(|(date, _)| *date)
+        .max()
+        .ok_or(BatteryError::EmptyData)?;
+
+    let (trend, label) = match fit_wear_trend(health) {
+        Some(trend) => {
+            let end_date = match trend.replacement_date() {
+                Some(date) => date.min(last_date + Duration::days(5 * 365)).max(last_date),
+                None => last_date,
+            };
+            let label = match trend.replacement_date() {
+                Some(date) => format!(
+                    "Wear trend, {:.1} % per year, reaches {REPLACEMENT_THRESHOLD} % on {}",
+                    trend.wear_per_year(),
+                    date.format("%Y-%m-%d")
+                ),
+                None => "Wear trend, not wearing".to_string(),
+            };
 
-    // removing all the entries after the end days
-    if let Some(number_of_days) = to_days_before {
-        // show prediction of future if to_days_before is 0 and prediction is true
-        // i.e. showing only if the graph up to current is shown
-        if !show_prediction || (to_days_before.is_some() && to_days_before.unwrap() != 0) {
-            let end_date = end_date - chrono::Duration::days(number_of_days);
-            sanitized_data.retain(|date, _| (date) < (&end_date))
+            (
+                vec![
+                    (trend.reference, trend.state_of_health),
+                    (end_date, trend.state_of_health_at(end_date)),
+                ],
+                label,
+            )
         }
-    }
-
-    /* Separating data into charge, discharge and unidentified portions */
-
-    sort_hashmap(&sanitized_data, &mut original_x_data, &mut original_y_data);
-    let x_data;
-    let y_data;
-    if interpolate {
-        let converted_datetimes = original_x_data
-            .iter()
-            .map(|dt| dt.timestamp() as f64)
-            .collect::<Vec<f64>>();
-        let converted_capacities = original_y_data
-            .iter()
-            .map(|capacity| *capacity as f64)
-            .collect::<Vec<f64>>();
-
-        let points = makima_spline::vec_to_points(&converted_datetimes, &converted_capacities);
-
-        let spline = Spline::from_vec(points);
-
-        // interpolating for each minute
-        let mut current_date = original_x_data.first().unwrap().to_owned();
-        let last_date = original_x_data.last().unwrap().to_owned();
-        let increment_by_minutes = 1;
-
-        // new vectors for x and y date
-        let mut interpolated_x_data: Vec<DateTime<Utc>> = Vec::new();
-        let mut interpolated_y_data: Vec<i32> = Vec::new();
-
-        while current_date <= current_date_time.min(last_date) {
-            let y_data: f64;
-
-            y_data = spline.sample(current_date.timestamp() as f64);
-
-            interpolated_x_data.push(current_date);
-
-            interpolated_y_data.push(y_data as i32);
-
-            current_date = current_date
-                .checked_add_signed(Duration::minutes(increment_by_minutes))
-                .unwrap();
+        None => (Vec::new(), String::new()),
+    };
 
-            let bat_record = BatteryHistoryRecord {
-                date_time: current_date,
-                capacity: y_data as i32,
-                state: match sanitized_data.get(&current_date) {
-                    Some(valid_record) => valid_record.state,
-                    None => ChargeState::Unknown,
-                },
-            };
+    plot_health(
+        &state_of_health,
+        (&trend, &label),
+        REPLACEMENT_THRESHOLD,
+        backend,
+    )
+}
 
-            // changing the key of the dictionary
-            sanitized_data.insert(current_date, bat_record);
-        }
+pub fn get_data_from_csv(
+    file_path: &str,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_data(file_path)
+}
 
-        x_data = interpolated_x_data;
-        y_data = interpolated_y_data;
-    } else {
-        x_data = original_x_data.clone();
-        y_data = original_y_data.clone();
-    }
+/// Reads any csv battery log described by the schema, see [CsvSchema::from_file]
+/// for reading the schema from a toml or json file.
+pub fn get_data_from_csv_with_schema(
+    file_path: &str,
+    schema: &CsvSchema,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_data_with_schema(file_path, schema)
+}
 
-    // the data must be sorted up to now, so we can separate into increasing and decreasing trends
-    let mut cur_index = 0;
-    let max_index = x_data.len() - 1;
+pub fn get_log_from_csv(
+    file_path: &str
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_log(file_path)
+}
 
-    let mut prev_capacity = sanitized_data
-        .get(&x_data[cur_index])
-        .expect("Couldn't get the capacity")
-        .capacity;
-    let mut cur_capacity;
+pub fn get_data_from_html(
+    file_path: &str,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_html_data(file_path)
+}
 
-    let mut previous_trend = PreviousVal::None;
+pub fn get_report_from_html(file_path: &str) -> Result<BatteryReport, BatteryError> {
+    get_html_report(file_path)
+}
 
-    let mut x_data_charging: Vec<Vec<DateTime<Utc>>> = Vec::new();
-    let mut y_data_charging: Vec<Vec<i32>> = Vec::new();
+/// The full charge and design capacities of the capacity history of the battery report html.
+pub fn get_health_from_html(file_path: &str) -> Result<Vec<HealthRecord>, BatteryError> {
+    Ok(health_from_capacity_history(
+        &get_html_report(file_path)?.capacity_history,
+    ))
+}
 
-    let mut x_data_discharging: Vec<Vec<DateTime<Utc>>> = Vec::new();
-    let mut y_data_discharging: Vec<Vec<i32>> = Vec::new();
+pub fn get_data_from_xml(
+    file_path: &str,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_xml_data(file_path)
+}
 
-    let mut x_data_none: Vec<Vec<DateTime<Utc>>> = Vec::new();
-    let mut y_data_none: Vec<Vec<i32>> = Vec::new();
+pub fn get_report_from_xml(file_path: &str) -> Result<XmlBatteryReport, BatteryError> {
+    get_xml_report(file_path)
+}
 
-    // pushing the first value to none
-    x_data_none.push(Vec::new());
-    y_data_none.push(Vec::new());
+/// The full charge and design capacities of the capacity history of the battery report xml.
+pub fn get_health_from_xml(file_path: &str) -> Result<Vec<HealthRecord>, BatteryError> {
+    Ok(health_from_capacity_history(
+        &get_xml_report(file_path)?.capacity_history,
+    ))
+}
 
-    x_data_none.last_mut().unwrap().push(x_data[cur_index]);
-    y_data_none
-        .last_mut()
-        .unwrap()
-        .push(sanitized_data.get(&x_data[cur_index]).unwrap().capacity);
+/// Reads the health csv logged by [SysfsSampler::append_health].
+pub fn get_health_from_csv(file_path: &str) -> Result<Vec<HealthRecord>, BatteryError> {
+    get_health_data(file_path)
+}
 
-    loop {
-        cur_index += 1;
-        if cur_index > max_index {
-            break;
-        }
-        cur_capacity = sanitized_data.get(&x_data[cur_index]);
+/// Reads a UPower `history-charge-*.dat` file, the percentages are converted into the unit
+/// of `full_capacity` when it is provided, e.g. from [get_info_from_upower].
+pub fn get_data_from_upower(
+    file_path: &str,
+    full_capacity: Option<Capacity>,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_upower_data(file_path, full_capacity)
+}
 
-        if cur_capacity.is_none() {
-            continue;
-        }
+pub fn get_history_from_upower(
+    dir_path: &str,
+) -> Result<HashMap<String, UpowerHistory>, BatteryError> {
+    get_upower_history(dir_path)
+}
 
-        // add to predicted vectors if the data is of the future
-        if x_data[cur_index] > current_date_time {
-            continue;
-        }
+pub fn get_info_from_upower(file_path: &str) -> Result<Vec<BatteryInfo>, BatteryError> {
+    get_upower_info(file_path)
+}
 
-        match previous_trend {
-            PreviousVal::None => {
-                if x_data_none.last_mut().is_none() || y_data_none.last_mut().is_none() {
-                    x_data_none.push(Vec::new());
-                    y_data_none.push(Vec::new());
-                }
-                x_data_none.last_mut().unwrap().push(x_data[cur_index]);
-                y_data_none
-                    .last_mut()
-                    .unwrap()
-                    .push(cur_capacity.unwrap().capacity);
-
-                previous_trend = match cur_capacity.unwrap().capacity.cmp(&prev_capacity) {
-                    std::cmp::Ordering::Less => {
-                        // creating a new vector for storing the following decreasing values
-                        x_data_discharging.push(Vec::new());
-                        y_data_discharging.push(Vec::new());
-
-                        // pushing the current value for connecting the dots otherwise the curve will be disconnected
-                        x_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(x_data[cur_index]);
-                        y_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(cur_capacity.unwrap().capacity);
-
-                        PreviousVal::Decreasing
-                    }
-                    std::cmp::Ordering::Equal => PreviousVal::None, // already created a vector for storing the following none values
-                    std::cmp::Ordering::Greater => {
-                        // creating a new vector for storing the following increasing values
-                        x_data_charging.push(Vec::new());
-                        y_data_charging.push(Vec::new());
-
-                        // pushing the current value for connecting the dots otherwise the curve will be disconnected
-                        x_data_charging.last_mut().unwrap().push(x_data[cur_index]);
-                        y_data_charging
-                            .last_mut()
-                            .unwrap()
-                            .push(cur_capacity.unwrap().capacity);
-
-                        PreviousVal::Increasing
-                    }
-                };
-            }
-            PreviousVal::Increasing => {
-                // if this also increases (or is equal), pushing into the last increasing vector
-                if cur_capacity.unwrap().capacity >= prev_capacity {
-                    previous_trend = PreviousVal::Increasing;
-                    x_data_charging.last_mut().unwrap().push(x_data[cur_index]);
-                    y_data_charging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                } else {
-                    previous_trend = PreviousVal::Decreasing;
-                    // creating a new vectors as this is separate decreasing curve
-                    x_data_discharging.push(Vec::new());
-                    y_data_discharging.push(Vec::new());
-
-                    if cur_index > 0 {
-                        // pushing the previous data to make the graph connected
-                        x_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(x_data[cur_index - 1]);
-                        y_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(sanitized_data.get(&x_data[cur_index - 1]).unwrap().capacity);
-                    }
-
-                    x_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(x_data[cur_index]);
-                    y_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                }
-            }
-            PreviousVal::Decreasing => {
-                // if this also decreases (or is equal), pushing into the last decreasing vector
-                if cur_capacity.unwrap().capacity <= prev_capacity {
-                    previous_trend = PreviousVal::Decreasing;
-                    x_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(x_data[cur_index]);
-                    y_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                } else {
-                    previous_trend = PreviousVal::Increasing;
-                    // creating a new vectors as this is separate increasing curve
-                    x_data_charging.push(Vec::new());
-                    y_data_charging.push(Vec::new());
-
-                    if cur_index > 0 {
-                        // pushing the previous data to make the graph connected
-                        x_data_charging
-                            .last_mut()
-                            .unwrap()
-                            .push(x_data[cur_index - 1]);
-                        y_data_charging
-                            .last_mut()
-                            .unwrap()
-                            .push(sanitized_data.get(&x_data[cur_index - 1]).unwrap().capacity);
-                    }
-
-                    x_data_charging.last_mut().unwrap().push(x_data[cur_index]);
-                    y_data_charging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                }
-            }
-        }
-        prev_capacity = cur_capacity.unwrap().capacity;
-    }
-    let mut x_data_predicted: Vec<DateTime<Utc>> = Vec::new();
-    let mut y_data_predicted: Vec<i32> = Vec::new();
-
-    /* Visualize the data */
-    if show_prediction && to_days_before.is_some() && to_days_before.unwrap() == 0 {
-        // sorting the predication
-        sort_hashmap(
-            &predicted_data,
-            &mut x_data_predicted,
-            &mut y_data_predicted,
-        )
-    }
+/// Reads the saved output of `pmset -g log`, the capacity of the records is the percentage.
+pub fn get_data_from_pmset(
+    file_path: &str,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_pmset_data(file_path)
+}
 
-    if interpolate {
-        start_battery_plot(
-            (&original_x_data, &original_y_data),
-            (&x_data_charging, &y_data_charging),
-            (&x_data_discharging, &y_data_discharging),
-            (&x_data_predicted, &y_data_predicted),
-            (&x_data_none, &y_data_none),
-            backend,
-            show_data_points,
-        )
-        .unwrap();
-    } else {
-        start_battery_plot(
-            (&x_data, &y_data),
-            (&x_data_charging, &y_data_charging),
-            (&x_data_discharging, &y_data_discharging),
-            (&x_data_predicted, &y_data_predicted),
-            (&x_data_none, &y_data_none),
-            backend,
-            show_data_points,
-        )
-        .unwrap();
-    }
+/// Reads the saved snapshots of `ioreg -rn AppleSmartBattery`, the capacity of the records is in mWh
+/// converted from mAh at the reported voltage.
+pub fn get_data_from_ioreg(
+    file_path: &str,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_ioreg_data(file_path)
+}
 
-    Ok(())
+pub fn get_batteries_from_ioreg(file_path: &str) -> Result<Vec<IoregBattery>, BatteryError> {
+    get_ioreg_batteries(file_path)
 }
 
-pub fn get_data_from_csv(
+/// Reads the saved output of `dumpsys batterystats --history`, the capacity of the records is the percentage.
+pub fn get_data_from_batterystats(
     file_path: &str,
-) -> Result<HashMap<DateTime<Utc>, BatteryHistoryRecord>, Box<dyn Error>> {
-    get_data(file_path)
+) -> Result<BatteryTimeSeries, BatteryError> {
+    get_batterystats_data(file_path)
 }
 
-pub fn get_log_from_csv(
-    file_path: &str
-) -> Result<HashMap<DateTime<Utc>, BatteryHistoryRecord>, Box<dyn Error>> {
-    get_log(file_path)
+pub fn get_history_from_batterystats(
+    file_path: &str,
+) -> Result<Vec<BatteryStatsEntry>, BatteryError> {
+    get_batterystats_history(file_path)
 }
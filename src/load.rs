@@ -0,0 +1,296 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::error::BatteryError;
+use crate::read_android::get_batterystats_data;
+use crate::read_data::{get_data, get_log};
+use crate::read_html::get_html_data;
+use crate::read_macos::{get_ioreg_data, get_pmset_data};
+use crate::read_upower::get_upower_data;
+use crate::read_xml::get_xml_data;
+use crate::time_series::BatteryTimeSeries;
+
+/// Number of bytes read from the start of the file for detecting the format
+const SNIFF_LENGTH: u64 = 64 * 1024;
+
+/// All the file formats which can be read by [load].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// csv with the headers date_time, capacity, state
+    HistoryCsv,
+    /// csv with the headers Date, Energy[J], Battery State
+    EnergyLogCsv,
+    /// `powercfg /batteryreport`
+    WindowsHtml,
+    /// `powercfg /batteryreport /xml`
+    WindowsXml,
+    /// `history-charge-*.dat` of UPower
+    UpowerHistory,
+    /// `pmset -g log`
+    Pmset,
+    /// `ioreg -rn AppleSmartBattery`
+    Ioreg,
+    /// `dumpsys batterystats --history`
+    BatteryStats,
+}
+
+impl InputFormat {
+    /// In the order in which they are tried, the more specific ones first.
+    pub const ALL: [InputFormat; 8] = [
+        InputFormat::WindowsXml,
+        InputFormat::WindowsHtml,
+        InputFormat::HistoryCsv,
+        InputFormat::EnergyLogCsv,
+        InputFormat::BatteryStats,
+        InputFormat::Ioreg,
+        InputFormat::Pmset,
+        InputFormat::UpowerHistory,
+    ];
+
+    /// Short name used for choosing the format, e.g. on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputFormat::HistoryCsv => "history-csv",
+            InputFormat::EnergyLogCsv => "energy-log-csv",
+            InputFormat::WindowsHtml => "html",
+            InputFormat::WindowsXml => "xml",
+            InputFormat::UpowerHistory => "upower",
+            InputFormat::Pmset => "pmset",
+            InputFormat::Ioreg => "ioreg",
+            InputFormat::BatteryStats => "batterystats",
+        }
+    }
+
+    /// Whether the start of the file looks like this format
+    fn matches(&self, sample: &str) -> bool {
+        let first_line = sample
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default()
+            .trim_start_matches('\u{feff}')
+            .trim();
+        let headers: Vec<&str> = first_line.split(',').map(|header| header.trim()).collect();
+
+        match self {
+            InputFormat::HistoryCsv => ["date_time", "capacity", "state"]
+                .iter()
+                .all(|header| headers.contains(header)),
+            InputFormat::EnergyLogCsv => ["Date", "Energy[J]", "Battery State"]
+                .iter()
+                .all(|header| headers.contains(header)),
+            InputFormat::WindowsHtml => {
+                let lowercase = sample.to_lowercase();
+                lowercase.contains("<html") && lowercase.contains("battery")
+            }
+            InputFormat::WindowsXml => {
+                first_line.starts_with("<?xml") && sample.contains("<BatteryReport")
+            }
+            InputFormat::UpowerHistory => {
+                let columns: Vec<&str> = first_line.split('\t').collect();
+                columns.len() == 3
+                    && columns[0].parse::<i64>().is_ok()
+                    && columns[1].parse::<f64>().is_ok()
+            }
+            InputFormat::Pmset => sample
+                .lines()
+                .any(|line| line.contains("Using Batt") || line.contains("Using AC")),
+            InputFormat::Ioreg => sample.contains("AppleSmartBattery"),
+            InputFormat::BatteryStats => sample.contains("RESET:TIME"),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InputFormat::HistoryCsv => "history csv (date_time,capacity,state)",
+            InputFormat::EnergyLogCsv => "energy log csv (Date,Energy[J],Battery State)",
+            InputFormat::WindowsHtml => "powercfg battery report html",
+            InputFormat::WindowsXml => "powercfg battery report xml",
+            InputFormat::UpowerHistory => "upower charge history",
+            InputFormat::Pmset => "pmset log",
+            InputFormat::Ioreg => "ioreg AppleSmartBattery",
+            InputFormat::BatteryStats => "android batterystats history",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = BatteryError;
+
+    /// Parses the [InputFormat::name] of the format
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        InputFormat::ALL
+            .iter()
+            .find(|format| format.name() == name)
+            .copied()
+            .ok_or_else(|| {
+                BatteryError::UnsupportedFormat(format!(
+                    "Unknown format {name}, expected one of: {}",
+                    InputFormat::ALL
+                        .iter()
+                        .map(|format| format.name())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                ))
+            })
+    }
+}
+
+/// Detects the format of the file in given path from its start.
+///
+/// # Returns
+/// The first of [InputFormat::ALL] which matches, or an error listing all the formats tried.
+pub fn detect_format(path: &str) -> Result<InputFormat, BatteryError> {
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut bytes)?;
+    let sample = String::from_utf8_lossy(&bytes);
+
+    detect(&sample).ok_or_else(|| {
+        BatteryError::UnsupportedFormat(format!(
+            "Couldn't detect the format of {path}, tried: {}",
+            InputFormat::ALL
+                .iter()
+                .map(|format| format.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+    })
+}
+
+/// The first of [InputFormat::ALL] which matches the start of a file
+fn detect(sample: &str) -> Option<InputFormat> {
+    InputFormat::ALL
+        .iter()
+        .find(|format| format.matches(sample))
+        .copied()
+}
+
+/// Reads the file in given path with the reader of given format.
+///
+/// The capacity of the records is in the unit of the source, i.e. percentage for
+/// [InputFormat::UpowerHistory], [InputFormat::Pmset] and [InputFormat::BatteryStats],
+/// J for [InputFormat::EnergyLogCsv] and mWh for the rest.
+pub fn load_as(path: &str, format: InputFormat) -> Result<BatteryTimeSeries, BatteryError> {
+    match format {
+        InputFormat::HistoryCsv => get_data(path),
+        InputFormat::EnergyLogCsv => get_log(path),
+        InputFormat::WindowsHtml => get_html_data(path),
+        InputFormat::WindowsXml => get_xml_data(path),
+        InputFormat::UpowerHistory => get_upower_data(path, None),
+        InputFormat::Pmset => get_pmset_data(path),
+        InputFormat::Ioreg => get_ioreg_data(path),
+        InputFormat::BatteryStats => get_batterystats_data(path),
+    }
+}
+
+/// Detects the format of the file in given path with [detect_format] and reads it with [load_as].
+pub fn load(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    load_as(path, detect_format(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn assert_detected(sample: &str, format: InputFormat) {
+        assert_eq!(detect(sample), Some(format), "{sample}");
+    }
+
+    #[test]
+    fn detects_history_csv() {
+        assert_detected(
+            "date_time,capacity,state\n2023-10-20 08:00:00,40000,Discharging\n",
+            InputFormat::HistoryCsv,
+        );
+    }
+
+    #[test]
+    fn detects_energy_log_csv() {
+        assert_detected(
+            "\u{feff}Date,Energy[J],Battery State\n2023-10-20 08:00:00,144000,Discharging\n",
+            InputFormat::EnergyLogCsv,
+        );
+    }
+
+    #[test]
+    fn detects_windows_html() {
+        assert_detected(
+            "<!DOCTYPE html>\n<HTML><HEAD><TITLE>Battery report</TITLE></HEAD>",
+            InputFormat::WindowsHtml,
+        );
+    }
+
+    #[test]
+    fn detects_windows_xml() {
+        assert_detected(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<BatteryReport>",
+            InputFormat::WindowsXml,
+        );
+    }
+
+    #[test]
+    fn detects_upower_history() {
+        assert_detected(
+            "1697788800\t45.000\tdischarging\n",
+            InputFormat::UpowerHistory,
+        );
+    }
+
+    #[test]
+    fn detects_pmset() {
+        assert_detected(
+            "2023-10-20 09:13:45 +0200 Sleep \tEntering Sleep state Using Batt (Charge:87%)\n",
+            InputFormat::Pmset,
+        );
+    }
+
+    #[test]
+    fn detects_ioreg() {
+        assert_detected(
+            "+-o AppleSmartBattery  <class AppleSmartBattery, id 0x100000254>\n",
+            InputFormat::Ioreg,
+        );
+    }
+
+    #[test]
+    fn detects_batterystats() {
+        assert_detected(
+            "Battery History:\n  0 (15) RESET:TIME: 2023-10-20-08-03-21\n",
+            InputFormat::BatteryStats,
+        );
+    }
+
+    #[test]
+    fn more_specific_formats_first() {
+        // the xml report has the html of its stylesheet
+        let xml = "<?xml version=\"1.0\"?>\n<BatteryReport><html>battery</html></BatteryReport>";
+        assert_eq!(detect(xml), Some(InputFormat::WindowsXml));
+
+        // the batterystats of a phone charging from a mac
+        let batterystats = "0 (15) RESET:TIME: 2023-10-20-08-03-21\n0 (2) 100 AppleSmartBattery\n";
+        assert_eq!(detect(batterystats), Some(InputFormat::BatteryStats));
+    }
+
+    #[test]
+    fn unknown_format_lists_the_formats_tried() {
+        let dir = TempDir::new("load-unknown");
+        let path = dir.write("notes.txt", "nothing about batteries\n");
+
+        match detect_format(&path) {
+            Err(BatteryError::UnsupportedFormat(message)) => {
+                assert!(message.contains(&path));
+                for format in InputFormat::ALL.iter() {
+                    assert!(message.contains(&format.to_string()), "{message}");
+                }
+            }
+            result => panic!("Expected an unsupported format, found {result:?}"),
+        }
+    }
+}
@@ -1,312 +1,418 @@
-mod plot;
-mod read_data;
-
-use chrono::{DateTime, Duration, Utc};
-use read_data::BatteryHistoryRecord;
-
-use crate::read_data::get_data;
-use crate::read_data::sort_hashmap;
-use crate::read_data::ChargeState;
-
-use crate::plot::start_battery_plot;
-use plotters::backend::BitMapBackend;
-
-use makima_spline::Spline;
-
-enum PreviousVal {
-    None,
-    Increasing,
-    Decreasing,
+use std::path::Path;
+use std::process::ExitCode;
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, Utc};
+use plotters::backend::{BitMapBackend, SVGBackend};
+
+use battery_data_analysis::{
+    load_all, plot, predict, BatteryError, Clock, InputFormat, PlotOptions, WindowBound,
+};
+
+const USAGE: &str = "\
+Plots the battery history along with its prediction.
+
+Usage: battery-data-analysis [OPTIONS] <INPUT>...
+
+Arguments:
+  <INPUT>...                 battery logs or reports, the records of all of them are plotted together
+
+Options:
+  -f, --format <FORMAT>      format of the inputs, detected from each file when not given
+                             [history-csv, energy-log-csv, html, xml, upower, pmset, ioreg, batterystats]
+  -o, --output <PATH>        [default: images/battery_report-0.png]
+  -t, --output-format <FMT>  png or svg, guessed from the extension of the output when not given
+  -s, --size <WxH>           size of the image in pixels [default: 4000x1000]
+      --from <DAYS|DATE>     start of the plot, in days before the last record or as a date [default: 14]
+      --to <DAYS|DATE>       end of the plot, in days before the last record or as a date [default: 0]
+      --now <DATE|last>      current time, e.g. for archived data [default: the time of this machine]
+      --interpolate          interpolate the records
+      --no-interpolate
+      --step <MINUTES>       time between the interpolated points [default: 1]
+      --points               show the dots on the records
+      --no-points
+      --prediction           show the prediction, only when the plot reaches the last record
+      --no-prediction
+  -h, --help
+
+The dates are in the local time unless an offset is given, e.g. 2023-10-20, \"2023-10-20 08:00\"
+or 2023-10-20T08:00:00+01:00.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Png,
+    Svg,
 }
 
-const FROM_DAYS_BEFORE: Option<i64> = Some(14);
-const TO_DAYS_BEFORE: Option<i64> = Some(0);
-const INTERPOLATE_DATA: bool = true;
-
-fn main() {
-    /* reading data from csv */
-
-    let data =
-        get_data("./assets/battery-history-csvs/batteryreport.csv").expect("Cannot load csv data");
+#[derive(Debug)]
+struct Args {
+    inputs: Vec<String>,
+    format: Option<InputFormat>,
+    output: String,
+    output_format: Option<OutputFormat>,
+    size: (u32, u32),
+    from: Option<WindowBound>,
+    to: Option<WindowBound>,
+    now: Option<Clock>,
+    interpolate: Option<bool>,
+    step: Option<Duration>,
+    show_data_points: Option<bool>,
+    show_prediction: Option<bool>,
+}
 
-    /* Separating data into charge, discharge and unidenfied portions */
-    // todo: separate into smaller portions so that proper visualizations can be done in graph
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("{error}\nSee --help for the usage.");
+            return ExitCode::from(2);
+        }
+    };
 
-    // debug
-    if data.is_empty() {
-        panic!("The provided data is empty.");
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
     }
+}
 
-    let end_date = data
-        .iter()
-        .reduce(|max_capacity_record, current| {
-            data.get_key_value(max_capacity_record.0.max(current.0))
-                .unwrap()
-        })
-        .unwrap()
-        .0
-        .to_owned();
-
-    let mut sanitized_data = data;
-    let mut original_x_data: Vec<DateTime<Utc>> = Vec::new();
-    let mut original_y_data: Vec<i32> = Vec::new();
+fn run(args: &Args) -> Result<(), BatteryError> {
+    /* the plot options */
 
-    // removing all the entries before the start days
-    if let Some(number_of_days) = FROM_DAYS_BEFORE {
-        let start_date = end_date - chrono::Duration::days(number_of_days);
-        sanitized_data.retain(|date, _| (date) > (&start_date));
+    let mut options = PlotOptions::default();
+    if let Some(clock) = args.now {
+        options.clock = clock;
     }
-
-    // removing all the entries after the end days
-    if let Some(number_of_days) = TO_DAYS_BEFORE {
-        let end_date = end_date - chrono::Duration::days(number_of_days);
-        sanitized_data.retain(|date, _| (date) < (&end_date))
+    if let Some(interpolate) = args.interpolate {
+        options.interpolate = interpolate;
+    }
+    if let Some(step) = args.step {
+        options.interpolation_step = step;
+    }
+    if let Some(show_data_points) = args.show_data_points {
+        options.show_data_points = show_data_points;
+    }
+    if let Some(show_prediction) = args.show_prediction {
+        options.show_prediction = show_prediction;
     }
 
-    /* Separating data into charge, discharge and unidenfied portions */
-
-    sort_hashmap(&sanitized_data, &mut original_x_data, &mut original_y_data);
+    if args.from.is_some() {
+        options.from = args.from;
+    }
+    if args.to.is_some() {
+        options.to = args.to;
+    }
 
-    let x_data;
-    let y_data;
-    if INTERPOLATE_DATA {
-        let converted_datetimes = original_x_data
-            .iter()
-            .map(|dt| dt.timestamp() as f64)
-            .collect::<Vec<f64>>();
-        let converted_capacities = original_y_data
-            .iter()
-            .map(|capacity| *capacity as f64)
-            .collect::<Vec<f64>>();
+    /* reading the inputs */
 
-        let points = makima_spline::vec_to_points(&converted_datetimes, &converted_capacities);
+    let data = load_all(&args.inputs, args.format)?;
 
-        let spline = Spline::from_vec(points);
+    // the graph is still useful without the prediction
+    let prediction = if options.show_prediction {
+        predict(&data)
+            .map_err(|error| eprintln!("Couldn't predict: {error}"))
+            .ok()
+    } else {
+        None
+    };
+    let (predicted, predicted_band) = match prediction {
+        Some(prediction) => (
+            prediction.predicted_data,
+            (prediction.interval.lower, prediction.interval.upper),
+        ),
+        None => Default::default(),
+    };
+
+    /* visualize the data */
+
+    if let Some(parent) = Path::new(&args.output).parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
 
-        // interpolating for each minute
-        let mut current_date = original_x_data.first().unwrap().to_owned();
-        let last_date = original_x_data.last().unwrap().to_owned();
-        let increment_by_minutes = 1;
+    let output_format = match args.output_format {
+        Some(output_format) => output_format,
+        None => match Path::new(&args.output)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("svg") => OutputFormat::Svg,
+            _ => OutputFormat::Png,
+        },
+    };
+
+    match output_format {
+        OutputFormat::Png => plot(
+            BitMapBackend::new(&args.output, args.size),
+            &data,
+            predicted,
+            predicted_band,
+            &options,
+        ),
+        OutputFormat::Svg => plot(
+            SVGBackend::new(&args.output, args.size),
+            &data,
+            predicted,
+            predicted_band,
+            &options,
+        ),
+    }
+}
 
-        // new vectors for x and y date
-        let mut interpolated_x_data: Vec<DateTime<Utc>> = Vec::new();
-        let mut interpolated_y_data: Vec<i32> = Vec::new();
+/// # Returns
+/// The arguments, or [None] when the help is asked for.
+fn parse_args<I: Iterator<Item = String>>(mut arguments: I) -> Result<Option<Args>, String> {
+    let mut args = Args {
+        inputs: Vec::new(),
+        format: None,
+        output: "images/battery_report-0.png".to_string(),
+        output_format: None,
+        size: (4000, 1000),
+        from: None,
+        to: None,
+        now: None,
+        interpolate: None,
+        step: None,
+        show_data_points: None,
+        show_prediction: None,
+    };
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or_else(|| format!("Missing the value of {argument}"))
+        };
+
+        match argument.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-f" | "--format" => {
+                args.format = Some(
+                    value()?
+                        .parse()
+                        .map_err(|error: BatteryError| error.to_string())?,
+                )
+            }
+            "-o" | "--output" => args.output = value()?,
+            "-t" | "--output-format" => {
+                args.output_format = Some(match value()?.as_str() {
+                    "png" => OutputFormat::Png,
+                    "svg" => OutputFormat::Svg,
+                    output_format => {
+                        return Err(format!(
+                            "Unknown output format {output_format}, expected png or svg"
+                        ))
+                    }
+                })
+            }
+            "-s" | "--size" => args.size = parse_size(&value()?)?,
+            "--from" => args.from = Some(parse_window_bound(&value()?)?),
+            "--to" => args.to = Some(parse_window_bound(&value()?)?),
+            "--now" => {
+                args.now = Some(match value()?.as_str() {
+                    "last" => Clock::LastSample,
+                    date => Clock::Fixed(parse_date(date)?),
+                })
+            }
+            "--interpolate" => args.interpolate = Some(true),
+            "--no-interpolate" => args.interpolate = Some(false),
+            "--step" => {
+                let step = value()?;
+                args.step = match step.parse::<i64>() {
+                    Ok(minutes) if minutes > 0 => Some(Duration::minutes(minutes)),
+                    _ => {
+                        return Err(format!(
+                            "Expected a positive number of minutes, found {step}"
+                        ))
+                    }
+                }
+            }
+            "--points" => args.show_data_points = Some(true),
+            "--no-points" => args.show_data_points = Some(false),
+            "--prediction" => args.show_prediction = Some(true),
+            "--no-prediction" => args.show_prediction = Some(false),
+            option if option.starts_with('-') && option.len() > 1 => {
+                return Err(format!("Unknown option {option}"))
+            }
+            input => args.inputs.push(input.to_string()),
+        }
+    }
 
-        while current_date <= last_date {
-            interpolated_x_data.push(current_date);
-            let interpolated_y = spline.sample(current_date.timestamp() as f64);
+    if args.inputs.is_empty() {
+        return Err("No input is given".to_string());
+    }
 
-            interpolated_y_data.push(interpolated_y as i32);
+    Ok(Some(args))
+}
 
-            current_date = current_date
-                .checked_add_signed(Duration::minutes(increment_by_minutes))
-                .unwrap();
+/// e.g. "4000x1000"
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|(width, height)| *width > 0 && *height > 0)
+        .ok_or_else(|| format!("Expected the size as WIDTHxHEIGHT, found {size}"))
+}
 
-            let bat_record = BatteryHistoryRecord {
-                date_time: current_date,
-                capacity: interpolated_y as i32,
-                state: match sanitized_data.get(&current_date) {
-                    Some(valid_record) => valid_record.state,
-                    None => ChargeState::Unknown,
-                },
-            };
+/// Number of days, or a date
+fn parse_window_bound(bound: &str) -> Result<WindowBound, String> {
+    match bound.parse::<i64>() {
+        Ok(days) if days >= 0 => Ok(WindowBound::DaysBefore(days)),
+        Ok(_) => Err(format!(
+            "Expected a non-negative number of days, found {bound}"
+        )),
+        Err(_) => parse_date(bound).map(WindowBound::Date),
+    }
+}
 
-            // changing the key of the dictionary
-            sanitized_data.insert(current_date, bat_record);
-        }
-        x_data = interpolated_x_data;
-        y_data = interpolated_y_data;
-    } else {
-        x_data = original_x_data.clone();
-        y_data = original_y_data.clone();
+/// e.g. "2023-10-20", "2023-10-20 08:00", "2023-10-20 08:00:00" in the local time
+/// or "2023-10-20T08:00:00+01:00"
+fn parse_date(date: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date_time.with_timezone(&Utc));
     }
 
-    // the data must be sorted up to now, so we can separate into increasing and decreasing trends
-    let mut cur_index = 0;
-    let max_index = x_data.len() - 1;
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .and_then(|date_time| date_time.and_local_timezone(Local).earliest())
+        .map(|date_time| date_time.with_timezone(&Utc))
+        .ok_or_else(|| format!("Expected a number of days or a date, found {date}"))
+}
 
-    let mut prev_capacity = sanitized_data
-        .get(&x_data[cur_index])
-        .expect("Couldn't get the capacity")
-        .capacity;
-    let mut cur_capacity;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
 
-    let mut previous_trend = PreviousVal::None;
+    fn parse(arguments: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(arguments.iter().map(|argument| argument.to_string()))
+    }
 
-    let mut x_data_charging: Vec<Vec<DateTime<Utc>>> = Vec::new();
-    let mut y_data_charging: Vec<Vec<i32>> = Vec::new();
+    fn local(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(year, month, day, 0, 0, 0)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
 
-    let mut x_data_discharging: Vec<Vec<DateTime<Utc>>> = Vec::new();
-    let mut y_data_discharging: Vec<Vec<i32>> = Vec::new();
+    #[test]
+    fn defaults() {
+        let args = parse(&["report.csv", "energy.csv"]).unwrap().unwrap();
+
+        assert_eq!(args.inputs, ["report.csv", "energy.csv"]);
+        assert_eq!(args.format, None);
+        assert_eq!(args.output, "images/battery_report-0.png");
+        assert_eq!(args.output_format, None);
+        assert_eq!(args.size, (4000, 1000));
+        assert_eq!((args.from, args.to), (None, None));
+        assert_eq!(args.now, None);
+        assert_eq!(args.interpolate, None);
+        assert_eq!(args.step, None);
+        assert_eq!(args.show_data_points, None);
+        assert_eq!(args.show_prediction, None);
+
+        assert!(parse(&["report.csv", "--help"]).unwrap().is_none());
+    }
 
-    let mut x_data_none: Vec<Vec<DateTime<Utc>>> = Vec::new();
-    let mut y_data_none: Vec<Vec<i32>> = Vec::new();
+    #[test]
+    fn toggles() {
+        let args = parse(&[
+            "--no-interpolate",
+            "--points",
+            "--no-points",
+            "--no-prediction",
+            "report.csv",
+        ])
+        .unwrap()
+        .unwrap();
 
-    loop {
-        cur_index += 1;
-        if cur_index > max_index {
-            break;
-        }
-        cur_capacity = sanitized_data.get(&x_data[cur_index]);
+        // the last one wins
+        assert_eq!(args.interpolate, Some(false));
+        assert_eq!(args.show_data_points, Some(false));
+        assert_eq!(args.show_prediction, Some(false));
 
-        if cur_capacity.is_none() {
-            continue;
-        }
+        let args = parse(&["--interpolate", "--prediction", "report.csv"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.interpolate, Some(true));
+        assert_eq!(args.show_prediction, Some(true));
+    }
 
-        match previous_trend {
-            PreviousVal::None => {
-                if x_data_none.last_mut().is_none() || y_data_none.last_mut().is_none() {
-                    x_data_none.push(Vec::new());
-                    y_data_none.push(Vec::new());
-                }
-                x_data_none.last_mut().unwrap().push(x_data[cur_index]);
-                y_data_none
-                    .last_mut()
-                    .unwrap()
-                    .push(cur_capacity.unwrap().capacity);
-
-                previous_trend = match cur_capacity.unwrap().capacity.cmp(&prev_capacity) {
-                    std::cmp::Ordering::Less => {
-                        // creating a new vector for storing the following decreasing values
-                        x_data_discharging.push(Vec::new());
-                        y_data_discharging.push(Vec::new());
-
-                        // pushing the current value for connecting the dots otherwise the curve will be disconnected
-                        x_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(x_data[cur_index]);
-                        y_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(cur_capacity.unwrap().capacity);
-
-                        PreviousVal::Decreasing
-                    }
-                    std::cmp::Ordering::Equal => PreviousVal::None, // already created a vector for storing the following none values
-                    std::cmp::Ordering::Greater => {
-                        // creating a new vector for storing the following increasing values
-                        x_data_charging.push(Vec::new());
-                        y_data_charging.push(Vec::new());
-
-                        // pushing the current value for connecting the dots otherwise the curve will be disconnected
-                        x_data_charging.last_mut().unwrap().push(x_data[cur_index]);
-                        y_data_charging
-                            .last_mut()
-                            .unwrap()
-                            .push(cur_capacity.unwrap().capacity);
-
-                        PreviousVal::Increasing
-                    }
-                };
-            }
-            PreviousVal::Increasing => {
-                // if this also increases (or is equal), pushing into the last increasing vector
-                if cur_capacity.unwrap().capacity >= prev_capacity {
-                    previous_trend = PreviousVal::Increasing;
-                    x_data_charging.last_mut().unwrap().push(x_data[cur_index]);
-                    y_data_charging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                } else {
-                    previous_trend = PreviousVal::Decreasing;
-                    // creating a new vectors as this is separate decreasing curve
-                    x_data_discharging.push(Vec::new());
-                    y_data_discharging.push(Vec::new());
-
-                    if cur_index > 0 {
-                        // pushing the previous data to make the graph connected
-                        x_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(x_data[cur_index - 1]);
-                        y_data_discharging
-                            .last_mut()
-                            .unwrap()
-                            .push(sanitized_data.get(&x_data[cur_index - 1]).unwrap().capacity);
-                    }
+    #[test]
+    fn window_in_days_or_dates() {
+        let args = parse(&["--from", "3", "--to", "0", "report.csv"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.from, Some(WindowBound::DaysBefore(3)));
+        assert_eq!(args.to, Some(WindowBound::DaysBefore(0)));
+
+        let args = parse(&[
+            "--from",
+            "2023-10-20",
+            "--to",
+            "2023-10-21T08:00:00+01:00",
+            "report.csv",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.from, Some(WindowBound::Date(local(2023, 10, 20))));
+        assert_eq!(
+            args.to,
+            Some(WindowBound::Date(
+                Utc.with_ymd_and_hms(2023, 10, 21, 7, 0, 0).unwrap()
+            ))
+        );
+
+        let error = parse(&["--from", "-1", "report.csv"]).unwrap_err();
+        assert!(error.contains("non-negative"), "{error}");
+        assert!(parse(&["--from", "yesterday", "report.csv"]).is_err());
+    }
 
-                    x_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(x_data[cur_index]);
-                    y_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                }
-            }
-            PreviousVal::Decreasing => {
-                // if this also decreases (or is equal), pushing into the last decreasing vector
-                if cur_capacity.unwrap().capacity <= prev_capacity {
-                    previous_trend = PreviousVal::Decreasing;
-                    x_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(x_data[cur_index]);
-                    y_data_discharging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                } else {
-                    previous_trend = PreviousVal::Increasing;
-                    // creating a new vectors as this is separate increasing curve
-                    x_data_charging.push(Vec::new());
-                    y_data_charging.push(Vec::new());
-
-                    if cur_index > 0 {
-                        // pushing the previous data to make the graph connected
-                        x_data_charging
-                            .last_mut()
-                            .unwrap()
-                            .push(x_data[cur_index - 1]);
-                        y_data_charging
-                            .last_mut()
-                            .unwrap()
-                            .push(sanitized_data.get(&x_data[cur_index - 1]).unwrap().capacity);
-                    }
+    #[test]
+    fn bad_size() {
+        assert_eq!(
+            parse(&["-s", "800x600", "report.csv"])
+                .unwrap()
+                .unwrap()
+                .size,
+            (800, 600)
+        );
 
-                    x_data_charging.last_mut().unwrap().push(x_data[cur_index]);
-                    y_data_charging
-                        .last_mut()
-                        .unwrap()
-                        .push(cur_capacity.unwrap().capacity);
-                }
-            }
+        for size in ["4000", "4000x", "0x1000", "wide x tall"] {
+            let error = parse(&["--size", size, "report.csv"]).unwrap_err();
+            assert!(error.contains("WIDTHxHEIGHT"), "{error}");
         }
-        prev_capacity = cur_capacity.unwrap().capacity;
     }
 
-    /* Visualize the data */
-    let file_name = format!("images/battery_report-{}.png", 0);
-
-    let drawing_backend = BitMapBackend::new(file_name.as_str(), (4000, 1000));
+    #[test]
+    fn missing_value() {
+        assert_eq!(
+            parse(&["report.csv", "--output"]).unwrap_err(),
+            "Missing the value of --output"
+        );
+    }
 
-    // predicted values
-    let predicted: (Vec<DateTime<Utc>>, Vec<i32>) = (Vec::new(), Vec::new());
+    #[test]
+    fn unknown_option() {
+        assert_eq!(
+            parse(&["--colour", "report.csv"]).unwrap_err(),
+            "Unknown option --colour"
+        );
+        assert_eq!(parse(&[]).unwrap_err(), "No input is given");
 
-    if INTERPOLATE_DATA {
-        start_battery_plot(
-            (&original_x_data, &original_y_data),
-            (&x_data_charging, &y_data_charging),
-            (&x_data_discharging, &y_data_discharging),
-            (&predicted.0, &predicted.1),
-            (&x_data_none, &y_data_none),
-            drawing_backend,
-            true,
-        )
-        .unwrap();
-    } else {
-        start_battery_plot(
-            (&x_data, &y_data),
-            (&x_data_charging, &y_data_charging),
-            (&x_data_discharging, &y_data_discharging),
-            (&predicted.0, &predicted.1),
-            (&x_data_none, &y_data_none),
-            drawing_backend,
-            true,
-        )
-        .unwrap();
+        // a lone dash is an input
+        assert_eq!(parse(&["-"]).unwrap().unwrap().inputs, ["-"]);
     }
 }
@@ -0,0 +1,398 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use makima_spline::Spline;
+use plotters::prelude::DrawingBackend;
+
+use crate::error::BatteryError;
+use crate::load::{load, load_as, InputFormat};
+use crate::plot::start_battery_plot;
+use crate::plot::PlotOptions;
+use crate::predict::Prediction;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::session::segment_sessions_with;
+use crate::session::Session;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+/// Start or end of the records kept by [filter].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowBound {
+    /// this many days before the last record
+    DaysBefore(i64),
+    Date(DateTime<Utc>),
+}
+
+impl WindowBound {
+    fn date(self, last: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            WindowBound::DaysBefore(days) => last - Duration::days(days),
+            WindowBound::Date(date) => date,
+        }
+    }
+}
+
+/// Everything drawn by [render].
+#[derive(Debug, Clone)]
+pub struct PlotData {
+    /// the records drawn as dots, when [PlotOptions::show_data_points] is set
+    pub records: BatteryTimeSeries,
+    /// the lines of the charging, discharging and unknown portions
+    pub sessions: Vec<Session>,
+    /// nothing is drawn when empty
+    pub predicted: BatteryTimeSeries,
+    /// the lower and the upper bounds of the prediction, nothing is drawn when empty
+    pub predicted_band: (BatteryTimeSeries, BatteryTimeSeries),
+    /// the current time, e.g. for [crate::XAxisLabels::RelativeToNow]
+    pub now: DateTime<Utc>,
+}
+
+impl PlotData {
+    /// The records and the sessions without any prediction
+    pub fn new(records: BatteryTimeSeries, sessions: Vec<Session>, now: DateTime<Utc>) -> Self {
+        PlotData {
+            records,
+            sessions,
+            predicted: BatteryTimeSeries::new(),
+            predicted_band: (BatteryTimeSeries::new(), BatteryTimeSeries::new()),
+            now,
+        }
+    }
+
+    /// Shows the predicted data along with its [crate::PredictionInterval] as a band around it
+    pub fn with_prediction(mut self, prediction: Prediction) -> Self {
+        self.predicted = prediction.predicted_data;
+        self.predicted_band = (prediction.interval.lower, prediction.interval.upper);
+        self
+    }
+}
+
+/// Reads all the files and merges their records, the records of the later files replace
+/// the ones of the earlier files at the same time.
+///
+/// # Parameters
+/// paths: the files to read
+///
+/// format: the format of all the files, detected from each file when [None]
+///
+/// # Returns
+/// The records with their capacities in the unit of the last record, or the error when
+/// a file can't be read or the units of the files can't be converted into each other.
+pub fn load_all<P: AsRef<str>>(
+    paths: &[P],
+    format: Option<InputFormat>,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut data = BatteryTimeSeries::new();
+
+    for path in paths.iter() {
+        data.merge(match format {
+            Some(format) => load_as(path.as_ref(), format)?,
+            None => load(path.as_ref())?,
+        });
+    }
+
+    match data.last() {
+        Some(last) => data.to_unit(last.capacity.unit()),
+        None => Ok(data),
+    }
+}
+
+/// Keeps the records between the bounds, both included.
+///
+/// The days are counted from the last record rather than from the end of a prediction,
+/// so the same records are kept whether the prediction is shown or not.
+///
+/// # Returns
+/// The records from `from` up to and including `to`, or the error when no record is left.
+pub fn filter(
+    data: &BatteryTimeSeries,
+    from: Option<WindowBound>,
+    to: Option<WindowBound>,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    let (first, last) = match (data.first(), data.last()) {
+        (Some(first), Some(last)) => (first.date_time, last.date_time),
+        _ => return Err(BatteryError::EmptyData),
+    };
+
+    let start = from.map_or(first, |from| from.date(last));
+    let end = to.map_or(last, |to| to.date(last));
+
+    if start > end {
+        return Err(BatteryError::EmptyDateRange { start, end });
+    }
+
+    let filtered = data.slice(start..=end);
+    if filtered.is_empty() {
+        return Err(BatteryError::EmptyDateRange { start, end });
+    }
+
+    Ok(filtered)
+}
+
+/// Interpolates the records with a makima spline at every step from the first record.
+///
+/// # Parameters
+/// data: the records, in the same unit
+///
+/// step: time between the interpolated points
+///
+/// until: no point is interpolated after this time (usually the current time), nor after the last record
+///
+/// # Returns
+/// The interpolated points, each with the state of the latest record at or before it.
+pub fn resample(
+    data: &BatteryTimeSeries,
+    step: Duration,
+    until: DateTime<Utc>,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    if step <= Duration::zero() {
+        return Err(BatteryError::InvalidOption(format!(
+            "The interpolation step must be positive, found {step}"
+        )));
+    }
+
+    let (first, last) = match (data.first(), data.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(BatteryTimeSeries::new()),
+    };
+    let unit = last.capacity.unit();
+
+    let converted_datetimes = data
+        .iter()
+        .map(|record| record.date_time.timestamp() as f64)
+        .collect::<Vec<f64>>();
+    let converted_capacities = data
+        .iter()
+        .map(|record| record.capacity.value())
+        .collect::<Vec<f64>>();
+
+    let points = makima_spline::vec_to_points(&converted_datetimes, &converted_capacities);
+
+    let spline = Spline::from_vec(points);
+
+    let mut current_date = first.date_time;
+    let last_date = until.min(last.date_time);
+
+    let mut interpolated_data = BatteryTimeSeries::new();
+
+    while current_date <= last_date {
+        let y_data = spline.sample(current_date.timestamp() as f64);
+
+        interpolated_data.insert(BatteryHistoryRecord {
+            date_time: current_date,
+            capacity: Capacity::new(y_data, unit),
+            // the state of the latest original record holds until the next one
+            state: match data.at_or_before(current_date) {
+                Some(valid_record) => valid_record.state,
+                None => ChargeState::Unknown,
+            },
+        });
+
+        current_date = match current_date.checked_add_signed(step) {
+            Some(next_date) => next_date,
+            None => break,
+        };
+    }
+
+    Ok(interpolated_data)
+}
+
+/// Whether the window ending at `to` reaches the last record, the prediction continues from it.
+fn reaches_last_record(data: &BatteryTimeSeries, to: Option<WindowBound>) -> bool {
+    match (data.last(), to) {
+        (Some(last), Some(to)) => to.date(last.date_time) >= last.date_time,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+/// Plots the records in the window of [PlotOptions::from] and [PlotOptions::to] along with
+/// the predicted data: [filter], [resample], [crate::segment_sessions_with] and [render].
+///
+/// # Parameters
+/// backend: the backend for plotting e.g. CairoBackend, SVGBackend, etc
+///
+/// data: the records to plot, in any unit which can be converted into the unit of the last record
+///
+/// predicted: shown after the last record when [PlotOptions::show_prediction] is set
+/// and the window reaches the last record
+///
+/// predicted_band: the lower and the upper bounds of the prediction, nothing is drawn when empty
+///
+/// options: the window, interpolation, colours and the other [PlotOptions] of the graph
+pub fn plot<'a, DB: DrawingBackend + 'a>(
+    backend: DB,
+    data: &BatteryTimeSeries,
+    predicted: BatteryTimeSeries,
+    predicted_band: (BatteryTimeSeries, BatteryTimeSeries),
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    // all the capacities are shown in the unit of the latest record
+    let unit = data.last().ok_or(BatteryError::EmptyData)?.capacity.unit();
+    let data = data.to_unit(unit)?;
+
+    // all the data after the current date is prediction
+    let current_date_time = options.clock.now(&data);
+
+    let records = filter(&data, options.from, options.to)?;
+
+    /* Separating data into charge, discharge and unidentified portions */
+
+    let data_series = if options.interpolate {
+        resample(&records, options.interpolation_step, current_date_time)?
+    } else {
+        records.clone()
+    };
+
+    // the data after the current date is of the future
+    let sessions = segment_sessions_with(
+        &data_series.slice(..=current_date_time),
+        &options.segmentation,
+    )?;
+
+    /* Visualize the data */
+
+    let mut plot = PlotData::new(records, sessions, current_date_time);
+
+    // the prediction continues from the last record, so it's only shown when the graph reaches it
+    if options.show_prediction && reaches_last_record(&data, options.to) {
+        plot.predicted = predicted;
+        plot.predicted_band = predicted_band;
+    }
+
+    render(backend, &plot, options)
+}
+
+/// Draws the records, the sessions and the prediction as described by the options.
+///
+/// The predicted data is converted into the unit of the records.
+pub fn render<'a, DB: DrawingBackend + 'a>(
+    backend: DB,
+    plot: &PlotData,
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    let converted = match plot.records.last() {
+        Some(last) => {
+            let unit = last.capacity.unit();
+            PlotData {
+                predicted: plot.predicted.to_unit(unit)?,
+                predicted_band: (
+                    plot.predicted_band.0.to_unit(unit)?,
+                    plot.predicted_band.1.to_unit(unit)?,
+                ),
+                ..plot.clone()
+            }
+        }
+        None => plot.clone(),
+    };
+
+    // the dots are only drawn on the original data, not on the interpolated data
+    start_battery_plot(&converted, backend, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// A record every day
+    fn days(count: i64) -> BatteryTimeSeries {
+        test_util::series(
+            Duration::days(1),
+            (0..count).map(|day| {
+                (
+                    Capacity::milliwatt_hours(50000.0 - 1000.0 * day as f64),
+                    ChargeState::Discharging,
+                )
+            }),
+        )
+    }
+
+    fn day(days: i64) -> DateTime<Utc> {
+        test_util::hour(24 * days)
+    }
+
+    fn dates(data: &BatteryTimeSeries) -> Vec<DateTime<Utc>> {
+        data.iter().map(|record| record.date_time).collect()
+    }
+
+    #[test]
+    fn filter_keeps_both_bounds() {
+        let data = days(6);
+
+        let filtered = filter(
+            &data,
+            Some(WindowBound::DaysBefore(2)),
+            Some(WindowBound::DaysBefore(1)),
+        )
+        .unwrap();
+        assert_eq!(dates(&filtered), vec![day(3), day(4)]);
+
+        let filtered = filter(
+            &data,
+            Some(WindowBound::Date(day(1))),
+            Some(WindowBound::Date(day(2))),
+        )
+        .unwrap();
+        assert_eq!(dates(&filtered), vec![day(1), day(2)]);
+    }
+
+    #[test]
+    fn filter_counts_days_from_last_record() {
+        let data = days(6);
+
+        let filtered = filter(&data, Some(WindowBound::DaysBefore(1)), None).unwrap();
+        assert_eq!(dates(&filtered), vec![day(4), day(5)]);
+
+        let filtered = filter(&data, None, Some(WindowBound::DaysBefore(4))).unwrap();
+        assert_eq!(dates(&filtered), vec![day(0), day(1)]);
+
+        assert_eq!(filter(&data, None, None).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn filter_without_records_in_window() {
+        let data = days(6);
+
+        assert!(matches!(
+            filter(
+                &data,
+                Some(WindowBound::DaysBefore(1)),
+                Some(WindowBound::DaysBefore(2))
+            ),
+            Err(BatteryError::EmptyDateRange { .. })
+        ));
+        assert!(matches!(
+            filter(
+                &data,
+                Some(WindowBound::Date(day(1) + Duration::hours(1))),
+                Some(WindowBound::Date(day(2) - Duration::hours(1)))
+            ),
+            Err(BatteryError::EmptyDateRange { .. })
+        ));
+        assert!(matches!(
+            filter(&BatteryTimeSeries::new(), None, None),
+            Err(BatteryError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn prediction_shown_when_window_reaches_last_record() {
+        let data = days(6);
+
+        assert!(reaches_last_record(&data, None));
+        assert!(reaches_last_record(&data, Some(WindowBound::DaysBefore(0))));
+        assert!(!reaches_last_record(
+            &data,
+            Some(WindowBound::DaysBefore(1))
+        ));
+        assert!(reaches_last_record(&data, Some(WindowBound::Date(day(5)))));
+        assert!(reaches_last_record(&data, Some(WindowBound::Date(day(7)))));
+        assert!(!reaches_last_record(
+            &data,
+            Some(WindowBound::Date(day(5) - Duration::minutes(1)))
+        ));
+        assert!(!reaches_last_record(&BatteryTimeSeries::new(), None));
+    }
+}
@@ -1,101 +1,589 @@
-use std::error::Error;
-
 use chrono::DateTime;
+use chrono::Duration;
+use chrono::FixedOffset;
+use chrono::Local;
 use chrono::Utc;
 use plotters::style::text_anchor::Pos;
 
 use plotters::prelude::*;
 
+use crate::clock::Clock;
+use crate::error::BatteryError;
+use crate::pipeline::PlotData;
+use crate::pipeline::WindowBound;
+use crate::session::SegmentationOptions;
+use crate::session::SessionKind;
+use crate::time_series::BatteryTimeSeries;
+
+/// What the labels of the x axis show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum XAxisLabels {
+    /// the date and time in [PlotOptions::time_zone] along with its offset, e.g. "2023-10-31 10:00 +01:00"
+    Absolute,
+    /// hours before the last record
+    RelativeToLastSample,
+    /// hours before the current time of [PlotOptions::clock], the predicted data is in negative hours
+    #[default]
+    RelativeToNow,
+    /// hours since the first plotted record
+    ElapsedSinceStart,
+}
+
+/// How the battery graph is drawn by [crate::battery_plot_pdf] and its variants.
+///
+/// The defaults draw the same graph as the binary: the last 14 days up to the last record,
+/// interpolated every minute, with the data points and the prediction.
+#[derive(Debug, Clone)]
+pub struct PlotOptions {
+    /// start of the shown records, see [crate::filter], from the first record when [None]
+    pub from: Option<WindowBound>,
+    /// end of the shown records, up to the last record when [None]. The prediction is only shown
+    /// when the end is at or after the last record
+    pub to: Option<WindowBound>,
+    pub interpolate: bool,
+    /// time between the interpolated points
+    pub interpolation_step: Duration,
+    /// dots on the original records
+    pub show_data_points: bool,
+    pub show_prediction: bool,
+    /// how the data is separated into the charging, discharging and unknown portions
+    pub segmentation: SegmentationOptions,
+    pub charging_color: RGBAColor,
+    pub discharging_color: RGBAColor,
+    pub unknown_color: RGBAColor,
+    pub prediction_color: RGBAColor,
+    /// opacity of the band of the prediction interval, relative to the prediction color
+    pub prediction_band_opacity: f64,
+    pub dot_color: RGBAColor,
+    /// color of the labels, the axes, the legend and the title
+    pub text_color: RGBAColor,
+    pub background_color: RGBAColor,
+    /// stroke width of the lines
+    pub stroke_width: u32,
+    pub axis_stroke_width: u32,
+    pub dot_radius: u32,
+    /// e.g. "sans-serif", "serif", "monospace" or the name of an installed font
+    pub font_family: String,
+    pub font_size: f64,
+    pub title_font_size: f64,
+    /// space for the labels of the x axis in pixels
+    pub x_label_area_size: u32,
+    /// space for the labels of the y axis in pixels
+    pub y_label_area_size: u32,
+    /// range of the x axis, fitted to the data when [None]
+    pub x_range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    pub x_labels: XAxisLabels,
+    /// offset of the [XAxisLabels::Absolute] labels, the local time zone of this machine when [None]
+    pub time_zone: Option<FixedOffset>,
+    /// decides which part of the data is of the past, and the time [XAxisLabels::RelativeToNow] is relative to
+    pub clock: Clock,
+    /// range of the y axis in the unit of the plotted capacities, fitted to the data when [None]
+    pub y_range: Option<(f64, f64)>,
+    /// padding below and above the fitted y axis, as a fraction of the minimum capacity
+    pub y_padding: f64,
+    /// description of the y axis e.g. "Capacity (mWh)", none when [None]
+    pub y_desc: Option<String>,
+    pub show_legend: bool,
+    pub title: Option<String>,
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        PlotOptions {
+            from: Some(WindowBound::DaysBefore(14)),
+            to: Some(WindowBound::DaysBefore(0)),
+            interpolate: true,
+            interpolation_step: Duration::minutes(1),
+            show_data_points: true,
+            show_prediction: true,
+            segmentation: SegmentationOptions::default(),
+            charging_color: GREEN.to_rgba(),
+            discharging_color: RED.to_rgba(),
+            unknown_color: BLACK.to_rgba(),
+            prediction_color: RGBAColor(240, 163, 10, 1.0),
+            prediction_band_opacity: 0.3,
+            dot_color: BLUE.to_rgba(),
+            text_color: WHITE.to_rgba(),
+            background_color: TRANSPARENT,
+            stroke_width: 5,
+            axis_stroke_width: 1,
+            dot_radius: 5,
+            font_family: "sans-serif".to_string(),
+            font_size: 20.0,
+            title_font_size: 40.0,
+            x_label_area_size: 100,
+            y_label_area_size: 70,
+            x_range: None,
+            x_labels: XAxisLabels::default(),
+            time_zone: None,
+            clock: Clock::default(),
+            y_range: None,
+            y_padding: 0.5,
+            y_desc: None,
+            show_legend: false,
+            title: None,
+        }
+    }
+}
+
+impl PlotOptions {
+    /// Shows the records from `from_days_before` up to `to_days_before` the last record,
+    /// all of them on the side which is [None].
+    pub fn with_window(self, from_days_before: Option<i64>, to_days_before: Option<i64>) -> Self {
+        self.with_window_bounds(
+            from_days_before.map(WindowBound::DaysBefore),
+            to_days_before.map(WindowBound::DaysBefore),
+        )
+    }
+
+    /// Shows the records between the bounds, e.g. between two dates,
+    /// see [PlotOptions::from] and [PlotOptions::to].
+    pub fn with_window_bounds(
+        mut self,
+        from: Option<WindowBound>,
+        to: Option<WindowBound>,
+    ) -> Self {
+        self.from = from;
+        self.to = to;
+        self
+    }
+
+    pub fn with_interpolation(mut self, interpolate: bool) -> Self {
+        self.interpolate = interpolate;
+        self
+    }
+
+    pub fn with_interpolation_step(mut self, step: Duration) -> Self {
+        self.interpolation_step = step;
+        self
+    }
+
+    pub fn with_data_points(mut self, show_data_points: bool) -> Self {
+        self.show_data_points = show_data_points;
+        self
+    }
+
+    pub fn with_prediction(mut self, show_prediction: bool) -> Self {
+        self.show_prediction = show_prediction;
+        self
+    }
+
+    pub fn with_segmentation(mut self, segmentation: SegmentationOptions) -> Self {
+        self.segmentation = segmentation;
+        self
+    }
+
+    /// Colors of the charging, discharging and unknown portions
+    pub fn with_session_colors<C: Color>(
+        mut self,
+        charging: C,
+        discharging: C,
+        unknown: C,
+    ) -> Self {
+        self.charging_color = charging.to_rgba();
+        self.discharging_color = discharging.to_rgba();
+        self.unknown_color = unknown.to_rgba();
+        self
+    }
+
+    pub fn with_prediction_color<C: Color>(mut self, color: C) -> Self {
+        self.prediction_color = color.to_rgba();
+        self
+    }
+
+    pub fn with_dot_color<C: Color>(mut self, color: C) -> Self {
+        self.dot_color = color.to_rgba();
+        self
+    }
+
+    pub fn with_text_color<C: Color>(mut self, color: C) -> Self {
+        self.text_color = color.to_rgba();
+        self
+    }
+
+    pub fn with_background_color<C: Color>(mut self, color: C) -> Self {
+        self.background_color = color.to_rgba();
+        self
+    }
+
+    pub fn with_stroke_width(mut self, stroke_width: u32) -> Self {
+        self.stroke_width = stroke_width;
+        self
+    }
+
+    pub fn with_dot_radius(mut self, dot_radius: u32) -> Self {
+        self.dot_radius = dot_radius;
+        self
+    }
+
+    pub fn with_font(mut self, family: &str, size: f64) -> Self {
+        self.font_family = family.to_string();
+        self.font_size = size;
+        self
+    }
+
+    /// Space for the labels of the x and the y axes in pixels
+    pub fn with_label_area_sizes(mut self, x: u32, y: u32) -> Self {
+        self.x_label_area_size = x;
+        self.y_label_area_size = y;
+        self
+    }
+
+    pub fn with_x_range(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.x_range = Some((start, end));
+        self
+    }
+
+    pub fn with_y_range(mut self, min: f64, max: f64) -> Self {
+        self.y_range = Some((min, max));
+        self
+    }
+
+    pub fn with_y_desc(mut self, y_desc: &str) -> Self {
+        self.y_desc = Some(y_desc.to_string());
+        self
+    }
+
+    pub fn with_x_labels(mut self, x_labels: XAxisLabels) -> Self {
+        self.x_labels = x_labels;
+        self
+    }
+
+    /// e.g. the time zone the data was recorded in, rather than the one of this machine
+    pub fn with_time_zone(mut self, time_zone: FixedOffset) -> Self {
+        self.time_zone = Some(time_zone);
+        self
+    }
+
+    /// e.g. [Clock::Fixed] or [Clock::LastSample], so that the graph doesn't change with the time it is drawn at.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_legend(mut self, show_legend: bool) -> Self {
+        self.show_legend = show_legend;
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    fn text_style(&self, size: f64) -> TextStyle<'_> {
+        TextStyle {
+            font: FontDesc::new(
+                FontFamily::from(self.font_family.as_str()),
+                size,
+                FontStyle::Normal,
+            ),
+            color: self.text_color.to_backend_color(),
+            pos: Pos::default(),
+        }
+    }
+}
+
 ///
 /// Plot the battery graph consisting of charging, discharging and unindentified portions.
 /// If proper separation is provided in each portions, visual distinction can be made otherwise
 /// ever curves will be spread across whole graph making only the last one visible
 ///
-/// # Paramaters
-/// id: unique graph id
+/// # Parameters
+/// plot: the records, the sessions, the prediction and the current time of [PlotOptions::clock],
+/// the prediction is shown as a line with a translucent band around it, nothing is drawn when they are empty
 ///
 /// backend: the backend for plotting e.g. CairoBackend, SVGBackend, etc
 ///
+/// options: the colors, sizes, ranges and the other [PlotOptions] of the graph
+///
 fn plot_battery_data_pdf<'a, DB: DrawingBackend + 'a>(
-    original_sorted_data: (&Vec<DateTime<Utc>>, &Vec<i32>),
-    charging: (&Vec<Vec<DateTime<Utc>>>, &Vec<Vec<i32>>),
-    discharging: (&Vec<Vec<DateTime<Utc>>>, &Vec<Vec<i32>>),
-    predicted: (&Vec<DateTime<Utc>>, &Vec<i32>),
-    none: (&Vec<Vec<DateTime<Utc>>>, &Vec<Vec<i32>>),
+    plot: &PlotData,
     backend: DB,
-    show_data_points: bool,
-) -> Result<(), Box<dyn Error + 'a>> {
-
-    // some constants
-    let stroke_width = 5;
-    let line_colors = [GREEN, RED, BLACK];
-    let dot_color = BLUE;
-    let prediction_color = RGBAColor(240, 163, 10, 1.0);
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    let original_data = &plot.records;
+    let sessions = &plot.sessions;
+    let predicted = &plot.predicted;
+    let predicted_band = (&plot.predicted_band.0, &plot.predicted_band.1);
+    let now = plot.now;
 
     let root_area = backend.into_drawing_area();
-    root_area.fill(&TRANSPARENT)?;
+    root_area.fill(&options.background_color)?;
 
     let mut start_date: DateTime<Utc> = DateTime::<Utc>::MAX_UTC;
     let mut end_date: DateTime<Utc> = DateTime::<Utc>::MIN_UTC;
 
-    let mut min_capacity = i32::MAX;
-    let mut max_capacity = i32::MIN;
+    let mut min_capacity = f64::MAX;
+    let mut max_capacity = f64::MIN;
 
-    let mut set_min_and_max = |x: (&Vec<DateTime<Utc>>, &Vec<i32>)| {
-        if !x.0.is_empty() {
-            start_date = start_date.min(x.0.first().unwrap().to_owned());
-            end_date = end_date.max(x.0.last().unwrap().to_owned());
+    let mut set_min_and_max = |x: &BatteryTimeSeries| {
+        if let (Some(first), Some(last)) = (x.first(), x.last()) {
+            start_date = start_date.min(first.date_time);
+            end_date = end_date.max(last.date_time);
         }
 
-        if !x.1.is_empty() {
-            min_capacity = min_capacity.min(x.1.iter().min().unwrap().to_owned());
-            max_capacity = max_capacity.max(x.1.iter().max().unwrap().to_owned());
+        for record in x.iter() {
+            min_capacity = min_capacity.min(record.capacity.value());
+            max_capacity = max_capacity.max(record.capacity.value());
         }
     };
-    charging
-        .0
+    sessions
         .iter()
-        .zip(charging.1.iter())
-        .for_each(&mut set_min_and_max);
-    discharging
-        .0
-        .iter()
-        .zip(discharging.1.iter())
-        .for_each(&mut set_min_and_max);
+        .for_each(|session| set_min_and_max(&session.records));
 
     set_min_and_max(predicted);
+    set_min_and_max(predicted_band.0);
+    set_min_and_max(predicted_band.1);
 
-    none.0
-        .iter()
-        .zip(none.1.iter())
-        .for_each(&mut set_min_and_max);
+    // if the start_date or end_date are still MAX_UTC and MIN_UTC respectively, there was nothing to plot
+    if start_date == DateTime::<Utc>::MAX_UTC || end_date == DateTime::<Utc>::MIN_UTC {
+        return Err(BatteryError::EmptyData);
+    }
 
-    // if the start_date or end_date are still MAX_UTC and MIN_UTC respectively, there was something wrong
-    // debug
-    // todo: return proper error when this happens
-    assert_ne!(start_date, DateTime::<Utc>::MAX_UTC);
-    assert_ne!(end_date, DateTime::<Utc>::MIN_UTC);
+    let first_date = start_date;
+    let last_sample_date = original_data
+        .last()
+        .map_or(end_date, |record| record.date_time);
+
+    let (start_date, end_date) = options.x_range.unwrap_or((start_date, end_date));
+    let (min_capacity, max_capacity) = options.y_range.unwrap_or((
+        min_capacity - min_capacity * options.y_padding,
+        max_capacity + min_capacity * options.y_padding,
+    ));
+
+    let mut chart_builder = ChartBuilder::on(&root_area);
+    chart_builder
+        .y_label_area_size(options.y_label_area_size)
+        .x_label_area_size(options.x_label_area_size);
+    if let Some(title) = &options.title {
+        chart_builder.caption(title, options.text_style(options.title_font_size));
+    }
+    let mut ctx =
+        chart_builder.build_cartesian_2d(start_date..end_date, min_capacity..max_capacity)?;
+
+    let x_label_formatter =
+        |x: &DateTime<Utc>| x_label(options, *x, first_date, last_sample_date, now);
+
+    let mut mesh = ctx.configure_mesh();
+    mesh.x_label_formatter(&x_label_formatter)
+        .disable_mesh()
+        .label_style(options.text_style(options.font_size))
+        .axis_desc_style(options.text_style(options.font_size))
+        .axis_style(ShapeStyle {
+            color: options.text_color,
+            stroke_width: options.axis_stroke_width,
+            filled: true,
+        })
+        .light_line_style(options.text_color);
+    if let Some(y_desc) = &options.y_desc {
+        mesh.y_desc(y_desc);
+    }
+    mesh.draw()?;
+
+    // draw the dots only on the original data, not on the interpolated data
+    if options.show_data_points {
+        ctx.draw_series(original_data.iter().map(|record| {
+            Circle::new(
+                (record.date_time, record.capacity.value()),
+                options.dot_radius,
+                ShapeStyle {
+                    color: options.dot_color.mix(1.0),
+                    filled: true,
+                    stroke_width: 1,
+                },
+            )
+        }))?;
+    }
+
+    // each kind is added to the legend only once
+    let mut labelled_kinds: Vec<SessionKind> = Vec::new();
+
+    for session in sessions.iter() {
+        let (line_color, label) = match session.kind {
+            SessionKind::Charging => (options.charging_color, "Charging"),
+            SessionKind::Discharging => (options.discharging_color, "Discharging"),
+            SessionKind::Unknown => (options.unknown_color, "Unknown"),
+        };
+
+        // the line
+        let series = ctx.draw_series(LineSeries::new(
+            session
+                .records
+                .iter()
+                .map(|record| (record.date_time, record.capacity.value())),
+            line_color.stroke_width(options.stroke_width),
+        ))?;
+
+        if options.show_legend && !labelled_kinds.contains(&session.kind) {
+            labelled_kinds.push(session.kind);
+            series
+                .label(label)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], line_color));
+        }
+    }
+    // the band of the prediction, below its line
+    if !predicted_band.0.is_empty() && !predicted_band.1.is_empty() {
+        ctx.draw_series(std::iter::once(Polygon::new(
+            predicted_band
+                .1
+                .iter()
+                .chain(predicted_band.0.iter().rev())
+                .map(|record| (record.date_time, record.capacity.value()))
+                .collect::<Vec<_>>(),
+            options
+                .prediction_color
+                .mix(options.prediction_band_opacity)
+                .filled(),
+        )))?;
+    }
+
+    // drawing the predicted data
+    let prediction_color = options.prediction_color;
+    let series = ctx.draw_series(LineSeries::new(
+        predicted
+            .iter()
+            .map(|record| (record.date_time, record.capacity.value())),
+        prediction_color.stroke_width(options.stroke_width),
+    ))?;
+
+    if options.show_legend {
+        if !predicted.is_empty() {
+            series.label("Prediction").legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], prediction_color)
+            });
+        }
+
+        ctx.configure_series_labels()
+            .label_font(options.text_style(options.font_size))
+            .border_style(options.text_color)
+            .draw()?;
+    }
+
+    root_area.present()?;
+    Ok(())
+}
+
+/// The label of the x axis at given time as described by [PlotOptions::x_labels]
+///
+/// # Parameters
+/// first: time of the first plotted record
+///
+/// last_sample: time of the last record
+///
+/// now: the current time of [PlotOptions::clock]
+fn x_label(
+    options: &PlotOptions,
+    x: DateTime<Utc>,
+    first: DateTime<Utc>,
+    last_sample: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> String {
+    const ABSOLUTE_FORMAT: &str = "%Y-%m-%d %H:%M %:z";
+
+    match options.x_labels {
+        XAxisLabels::Absolute => match options.time_zone {
+            Some(time_zone) => x.with_timezone(&time_zone).format(ABSOLUTE_FORMAT),
+            None => x
+                .with_timezone(&Local)
+                .fixed_offset()
+                .format(ABSOLUTE_FORMAT),
+        }
+        .to_string(),
+        XAxisLabels::RelativeToLastSample => {
+            format!("{} hrs", last_sample.signed_duration_since(x).num_hours())
+        }
+        XAxisLabels::RelativeToNow => {
+            format!("{} hrs", now.signed_duration_since(x).num_hours())
+        }
+        XAxisLabels::ElapsedSinceStart => {
+            format!("+{} hrs", x.signed_duration_since(first).num_hours())
+        }
+    }
+}
+
+/// Plot single graph of the whole data
+/// and plot smaller graphs of various sections (turned off because it required long time)
+///
+/// todo: provide interface to control the size of each small graph
+///
+pub fn start_battery_plot<'a, DB: DrawingBackend + 'a>(
+    plot: &PlotData,
+    backend: DB,
+    options: &PlotOptions,
+) -> Result<(), BatteryError> {
+    // the whole graph
+    plot_battery_data_pdf(plot, backend, options)?;
+
+    Ok(())
+}
+
+///
+/// Plot the state of health of the battery over time along with its fitted wear trend
+/// and the threshold below which the battery should be replaced.
+///
+/// # Parameters
+/// state_of_health: the state of health in percentage at each time
+///
+/// trend: the points of the fitted trend along with its label, no line is drawn when empty
+///
+/// threshold: the state of health in percentage at which the battery should be replaced
+///
+/// backend: the backend for plotting e.g. CairoBackend, SVGBackend, etc
+///
+pub fn plot_health<'a, DB: DrawingBackend + 'a>(
+    state_of_health: &[(DateTime<Utc>, f64)],
+    trend: (&[(DateTime<Utc>, f64)], &str),
+    threshold: f64,
+    backend: DB,
+) -> Result<(), BatteryError> {
+    // some constants
+    let stroke_width = 5;
+    let line_color = GREEN;
+    let dot_color = BLUE;
+    let trend_color = RGBAColor(240, 163, 10, 1.0);
+    let threshold_color = RED;
+
+    let root_area = backend.into_drawing_area();
+    root_area.fill(&TRANSPARENT)?;
+
+    let points = || state_of_health.iter().chain(trend.0.iter());
+
+    let start_date = points()
+        .map(|(date, _)| *date)
+        .min()
+        .ok_or(BatteryError::EmptyData)?;
+    let end_date = points().map(|(date, _)| *date).max().unwrap();
+
+    let min_percentage = points()
+        .map(|(_, percentage)| *percentage)
+        .fold(threshold, f64::min);
+    let max_percentage = points()
+        .map(|(_, percentage)| *percentage)
+        .fold(100.0, f64::max);
 
     let mut ctx = ChartBuilder::on(&root_area)
-        .y_label_area_size(70)
+        .y_label_area_size(100)
         .x_label_area_size(100)
-        // .caption("Battery Usage History", ("sans-serif", 40))
         .build_cartesian_2d(
             start_date..end_date,
-            (min_capacity as f64 - min_capacity as f64 * 0.5) as i32
-                ..(max_capacity as f64 + min_capacity as f64 * 0.5) as i32,
+            (min_percentage - 5.0)..(max_percentage + 5.0),
         )?;
 
     ctx.configure_mesh()
-        .x_label_formatter(&|x| {
-            format!("{} hrs", (Utc::now().signed_duration_since(x).num_hours()))
-        })
+        .x_label_formatter(&|x| x.format("%Y-%m-%d").to_string())
+        .y_desc("State of health (%)")
         .disable_mesh()
         .label_style(TextStyle {
             font: FontDesc::new(FontFamily::SansSerif, 20.0, FontStyle::Normal),
             color: WHITE.to_backend_color(),
             pos: Pos::default(),
         })
+        .axis_desc_style(TextStyle {
+            font: FontDesc::new(FontFamily::SansSerif, 20.0, FontStyle::Normal),
+            color: WHITE.to_backend_color(),
+            pos: Pos::default(),
+        })
         .axis_style(ShapeStyle {
             color: WHITE.to_rgba(),
             stroke_width: 1,
@@ -104,85 +592,125 @@ fn plot_battery_data_pdf<'a, DB: DrawingBackend + 'a>(
         .light_line_style(WHITE)
         .draw()?;
 
-    // draw the dots only on the original data, not on the interpolated data
-    if show_data_points {
-        ctx.draw_series(
-            original_sorted_data
-                .0
-                .iter()
-                .zip(original_sorted_data.1.iter())
-                .map(|(date, capacity)| {
-                    Circle::new(
-                        (*date, *capacity),
-                        5,
-                        ShapeStyle {
-                            color: dot_color.mix(1.0),
-                            filled: true,
-                            stroke_width: 1,
-                        },
-                    )
-                }),
-        )?;
-    }
+    ctx.draw_series(LineSeries::new(
+        [(start_date, threshold), (end_date, threshold)],
+        threshold_color.stroke_width(1),
+    ))?
+    .label(format!("Replacement at {threshold} %"))
+    .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], threshold_color));
 
-    for (i, state) in [charging, discharging, none].iter().enumerate() {
-        for (trend_charge, trend_state) in state.0.iter().zip(state.1.iter()) {
-            // the line
-            ctx.draw_series(LineSeries::new(
-                trend_charge
-                    .iter()
-                    .zip(trend_state.iter())
-                    .map(|(date, capacity)| (*date, *capacity)),
-                line_colors[i].stroke_width(stroke_width),
-            ))?;
-        }
-    }
-    // drawing the predicted data
     ctx.draw_series(LineSeries::new(
-        predicted.0
-            .iter()
-            .zip(predicted.1.iter())
-            .map(|(date, capacity)| (*date, *capacity)),
-        prediction_color.stroke_width(stroke_width),
-    ))?;
+        state_of_health.iter().copied(),
+        line_color.stroke_width(stroke_width),
+    ))?
+    .label("State of health")
+    .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], line_color));
+
+    ctx.draw_series(state_of_health.iter().map(|point| {
+        Circle::new(
+            *point,
+            5,
+            ShapeStyle {
+                color: dot_color.mix(1.0),
+                filled: true,
+                stroke_width: 1,
+            },
+        )
+    }))?;
+
+    if !trend.0.is_empty() {
+        ctx.draw_series(LineSeries::new(
+            trend.0.iter().copied(),
+            trend_color.stroke_width(stroke_width),
+        ))?
+        .label(trend.1)
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], trend_color));
+    }
+
+    ctx.configure_series_labels()
+        .label_font(TextStyle {
+            font: FontDesc::new(FontFamily::SansSerif, 20.0, FontStyle::Normal),
+            color: WHITE.to_backend_color(),
+            pos: Pos::default(),
+        })
+        .border_style(WHITE)
+        .draw()?;
 
     root_area.present()?;
     Ok(())
 }
 
-/// Plot single graph of the whole data
-/// and plot smaller graphs of various sections (turned off because it required long time)
-///
-/// todo: provide interface to control the size of each small graph
-///
-pub fn start_battery_plot<'a, DB: DrawingBackend + 'a>(
-    original_sorted_data: (&Vec<DateTime<Utc>>, &Vec<i32>),
-    charging: (&Vec<Vec<DateTime<Utc>>>, &Vec<Vec<i32>>),
-    discharging: (&Vec<Vec<DateTime<Utc>>>, &Vec<Vec<i32>>),
-    predicted: (&Vec<DateTime<Utc>>, &Vec<i32>),
-    none: (&Vec<Vec<DateTime<Utc>>>, &Vec<Vec<i32>>),
-    backend: DB,
-    show_data_points: bool,
-) -> Result<(), Box<dyn Error + 'a>> {
-    let x_data_charging = charging.0;
-    let y_data_charging = charging.1;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let x_data_discharging = discharging.0;
-    let y_data_discharging = discharging.1;
+    fn date_time(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
 
-    let x_data_none = none.0;
-    let y_data_none = none.1;
+    fn labels(options: &PlotOptions, times: &[&str]) -> Vec<String> {
+        let first = date_time("2023-10-31T00:00:00Z");
+        let last_sample = date_time("2023-10-31T12:00:00Z");
+        let now = date_time("2023-10-31T15:00:00Z");
 
-    // the whole graph
-    plot_battery_data_pdf(
-        original_sorted_data,
-        (x_data_charging, y_data_charging),
-        (x_data_discharging, y_data_discharging),
-        (predicted.0, predicted.1),
-        (x_data_none, y_data_none),
-        backend,
-        show_data_points,
-    )?;
+        times
+            .iter()
+            .map(|x| x_label(options, date_time(x), first, last_sample, now))
+            .collect()
+    }
 
-    Ok(())
+    const TIMES: [&str; 3] = [
+        "2023-10-31T09:00:00Z",
+        "2023-10-31T12:00:00Z",
+        "2023-10-31T18:30:00Z",
+    ];
+
+    #[test]
+    fn absolute_labels() {
+        let options = PlotOptions::default()
+            .with_x_labels(XAxisLabels::Absolute)
+            .with_time_zone(FixedOffset::east_opt(3600).unwrap());
+        assert_eq!(
+            labels(&options, &TIMES),
+            [
+                "2023-10-31 10:00 +01:00",
+                "2023-10-31 13:00 +01:00",
+                "2023-10-31 19:30 +01:00"
+            ]
+        );
+
+        let options = options.with_time_zone(FixedOffset::west_opt(5 * 3600).unwrap());
+        assert_eq!(labels(&options, &TIMES[..1]), ["2023-10-31 04:00 -05:00"]);
+
+        // the local time zone by default
+        let options = PlotOptions::default().with_x_labels(XAxisLabels::Absolute);
+        assert_eq!(
+            labels(&options, &TIMES[..1]),
+            [date_time(TIMES[0])
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M %:z")
+                .to_string()]
+        );
+    }
+
+    #[test]
+    fn relative_to_now_labels() {
+        // the default, the predicted data is in negative hours
+        assert_eq!(PlotOptions::default().x_labels, XAxisLabels::RelativeToNow);
+        assert_eq!(
+            labels(&PlotOptions::default(), &TIMES),
+            ["6 hrs", "3 hrs", "-3 hrs"]
+        );
+    }
+
+    #[test]
+    fn relative_to_reference_labels() {
+        let options = PlotOptions::default().with_x_labels(XAxisLabels::RelativeToLastSample);
+        assert_eq!(labels(&options, &TIMES), ["3 hrs", "0 hrs", "-6 hrs"]);
+
+        let options = PlotOptions::default().with_x_labels(XAxisLabels::ElapsedSinceStart);
+        assert_eq!(labels(&options, &TIMES), ["+9 hrs", "+12 hrs", "+18 hrs"]);
+    }
 }
@@ -0,0 +1,591 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use crate::error::BatteryError;
+use crate::predictor::Predictor;
+use crate::predictor::ProfilePredictor;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::session::segment_sessions_with;
+use crate::session::SegmentationOptions;
+use crate::session::Session;
+use crate::session::SessionKind;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+use crate::units::CapacityUnit;
+
+/// Number of bins of the capacity used for the shape of the sessions, i.e. 10% each
+pub(crate) const LEVEL_BINS: usize = 10;
+
+/// How far the usage profile and the session shape may change the recent rate
+pub(crate) const MIN_FACTOR: f64 = 0.25;
+pub(crate) const MAX_FACTOR: f64 = 4.0;
+
+/// How [predict_with] and the [Predictor]s forecast the current session.
+#[derive(Debug, Clone)]
+pub struct PredictionOptions {
+    /// the recent rate is measured over this much of the end of the current session
+    pub recent_window: Duration,
+    /// time between the predicted records
+    pub step: Duration,
+    /// the prediction of [predict_with] stops after this even when the battery is neither empty nor full
+    pub max_horizon: Duration,
+    /// the capacity at which the battery is empty, zero when [None]
+    pub empty_capacity: Option<Capacity>,
+    /// the capacity at which the battery is full, when [None] it is 100% for the histories
+    /// in percentage and the highest capacity of the history otherwise
+    pub full_capacity: Option<Capacity>,
+    /// how the history is split into the charging and discharging sessions
+    pub segmentation: SegmentationOptions,
+    /// share of the earlier sessions whose rates are within the [PredictionInterval], between 0 and 1
+    pub confidence: f64,
+}
+
+impl Default for PredictionOptions {
+    fn default() -> Self {
+        PredictionOptions {
+            recent_window: Duration::minutes(30),
+            step: Duration::minutes(1),
+            max_horizon: Duration::days(2),
+            empty_capacity: None,
+            full_capacity: None,
+            segmentation: SegmentationOptions::default(),
+            confidence: 0.8,
+        }
+    }
+}
+
+/// Forecast of the current session from the last record of the history.
+#[derive(Debug, Clone)]
+pub struct Prediction {
+    /// whether the battery is expected to be charging or discharging
+    pub kind: SessionKind,
+    /// time of the last record of the history, where the prediction starts
+    pub start: DateTime<Utc>,
+    /// the predicted rate at the start per hour, in the unit of the predicted data
+    pub rate: f64,
+    /// [None] when charging or when the battery isn't empty within the horizon
+    pub time_to_empty: Option<Duration>,
+    /// [None] when discharging or when the battery isn't full within the horizon
+    pub time_to_full: Option<Duration>,
+    /// the predicted records starting with the last record of the history so that the curves are connected,
+    /// in the unit of the last record, e.g. for the `predicted_data` of [crate::battery_plot_pdf]
+    pub predicted_data: BatteryTimeSeries,
+    pub interval: PredictionInterval,
+}
+
+/// Range of the prediction from the spread of the average rates of the earlier sessions of the same kind,
+/// the prediction itself is always within it.
+///
+/// It has no width when there are no earlier sessions of the same kind.
+#[derive(Debug, Clone)]
+pub struct PredictionInterval {
+    /// share of the earlier sessions whose rates are within the interval
+    pub confidence: f64,
+    /// the lowest expected capacity at each time, the bounds last as long as the predicted data
+    pub lower: BatteryTimeSeries,
+    /// the highest expected capacity at each time
+    pub upper: BatteryTimeSeries,
+    /// time to empty or full with the faster rate
+    pub earliest: Option<Duration>,
+    /// time to empty or full with the slower rate, [None] when it isn't reached within the horizon
+    pub latest: Option<Duration>,
+}
+
+impl Prediction {
+    /// When the battery is expected to be empty
+    pub fn empty_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.start + self.time_to_empty?)
+    }
+
+    /// When the battery is expected to be full
+    pub fn full_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.start + self.time_to_full?)
+    }
+}
+
+/// Predicts the current session of the history with the default [PredictionOptions].
+pub fn predict(data: &BatteryTimeSeries) -> Result<Prediction, BatteryError> {
+    predict_with(data, &PredictionOptions::default())
+}
+
+/// Forecasts the remaining time to empty or to full of the current session, i.e. the one the last
+/// record of the history belongs to, with the [ProfilePredictor] up to the maximum horizon.
+///
+/// The recent rate of the current session is extended into the future, adjusted by how the rate of
+/// the earlier sessions of the same kind changes with the hour of the day and with the capacity,
+/// e.g. the usage in the evening or the slower charging near full. When the current session is too short
+/// for a recent rate, the average rate of the earlier sessions of the same kind is used.
+///
+/// # Parameters
+/// data: the history, e.g. returned by [crate::read_data::get_data] or [crate::read_data::get_log]
+///
+/// options: see [PredictionOptions]
+///
+/// # Returns
+/// The [Prediction] in the unit of the last record, or the error when the history is empty, its capacities
+/// can't be converted into the same unit or there is no rate to extend.
+pub fn predict_with(
+    data: &BatteryTimeSeries,
+    options: &PredictionOptions,
+) -> Result<Prediction, BatteryError> {
+    let mut predictor = ProfilePredictor::new(options.clone());
+    predictor.fit(data)?;
+    predictor.forecast(options.max_horizon)
+}
+
+/// The history prepared for forecasting its current session, shared by the [Predictor]s
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    /// the last record, where the forecast starts
+    pub last: BatteryHistoryRecord,
+    pub empty: f64,
+    pub full: f64,
+    /// the kind of the current session, or the expected one when it is still unknown
+    pub kind: SessionKind,
+    pub current: Session,
+    /// the earlier sessions of the same kind as the current one, in the unit of the last record
+    pub earlier: Vec<Session>,
+    pub options: PredictionOptions,
+}
+
+impl History {
+    pub fn new(
+        data: &BatteryTimeSeries,
+        options: &PredictionOptions,
+    ) -> Result<Self, BatteryError> {
+        let last = data.last().ok_or(BatteryError::EmptyData)?.clone();
+        let unit = last.capacity.unit();
+
+        if options.step <= Duration::zero() {
+            return Err(BatteryError::InvalidOption(
+                "The step of the prediction must be positive".to_string(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&options.confidence) {
+            return Err(BatteryError::InvalidOption(format!(
+                "The confidence must be between 0 and 1, found {}",
+                options.confidence
+            )));
+        }
+
+        // the rates can only be compared in the same unit
+        let history = data.to_unit(unit)?;
+
+        let to_unit = |capacity: Capacity| {
+            capacity.to(unit).map(|capacity| capacity.value()).ok_or(
+                BatteryError::IncompatibleUnits {
+                    from: capacity.unit(),
+                    to: unit,
+                },
+            )
+        };
+        let empty = match options.empty_capacity {
+            Some(capacity) => to_unit(capacity)?,
+            None => 0.0,
+        };
+        let full = match options.full_capacity {
+            // the battery is as full as it gets while it stays plugged in
+            _ if last.state == ChargeState::FullyCharged => last.capacity.value(),
+            Some(capacity) => to_unit(capacity)?,
+            None if unit == CapacityUnit::Percent => 100.0,
+            None => history
+                .iter()
+                .map(|record| record.capacity.value())
+                .fold(f64::MIN, f64::max),
+        };
+
+        let mut sessions = segment_sessions_with(&history, &options.segmentation)?;
+        let current = sessions.pop().expect("The history isn't empty");
+
+        let kind = match (current.kind, last.state) {
+            (_, ChargeState::FullyCharged) => SessionKind::Charging,
+            (SessionKind::Unknown, ChargeState::Charging) => SessionKind::Charging,
+            (SessionKind::Unknown, ChargeState::Discharging) => SessionKind::Discharging,
+            (SessionKind::Unknown, state) => {
+                return Err(BatteryError::Prediction(format!(
+                    "The battery is neither charging nor discharging, its last state is {state:?} \
+                     and its capacity hasn't changed"
+                )))
+            }
+            (kind, _) => kind,
+        };
+        sessions.retain(|session| session.kind == kind);
+
+        Ok(History {
+            last,
+            empty,
+            full,
+            kind,
+            current,
+            earlier: sessions,
+            options: options.clone(),
+        })
+    }
+
+    /// Whether the rate is finite and in the direction of the session, i.e. positive while charging
+    pub fn is_valid(&self, rate: f64) -> bool {
+        let expected_sign = match self.kind {
+            SessionKind::Charging => 1.0,
+            _ => -1.0,
+        };
+
+        rate.is_finite() && rate * expected_sign > 0.0
+    }
+
+    /// Rate of the end of the current session, or of the whole current session when the end is too short
+    pub fn recent_rate(&self) -> Option<f64> {
+        let recent = self
+            .current
+            .records
+            .slice(self.last.date_time - self.options.recent_window..);
+
+        match (recent.first(), recent.last()) {
+            (Some(first), Some(last)) if last.date_time > first.date_time => Some(
+                (last.capacity.value() - first.capacity.value())
+                    / ((last.date_time - first.date_time).num_seconds() as f64 / 3600.0),
+            ),
+            _ => None,
+        }
+        .filter(|rate| self.is_valid(*rate))
+        .or(self
+            .current
+            .average_rate()
+            .filter(|rate| self.is_valid(*rate)))
+    }
+
+    /// Average rate of all the earlier sessions together
+    pub fn earlier_rate(&self) -> Option<f64> {
+        let hours: f64 = self
+            .earlier
+            .iter()
+            .map(|session| session.duration().num_seconds() as f64 / 3600.0)
+            .sum();
+        let delta: f64 = self
+            .earlier
+            .iter()
+            .filter_map(|session| session.energy_delta())
+            .map(|delta| delta.value())
+            .sum();
+
+        Some(delta / hours).filter(|rate| self.is_valid(*rate))
+    }
+
+    /// Whether the battery is full and holds its capacity, so that no rate is needed
+    pub fn is_fully_charged(&self) -> bool {
+        self.last.state == ChargeState::FullyCharged
+    }
+
+    pub fn no_rate_error(&self) -> BatteryError {
+        BatteryError::Prediction(format!(
+            "There is no {} rate to predict the battery from",
+            match self.kind {
+                SessionKind::Charging => "charging",
+                _ => "discharging",
+            }
+        ))
+    }
+
+    pub fn level_bin(&self, capacity: f64) -> usize {
+        if self.full <= self.empty {
+            return 0;
+        }
+
+        let fraction = (capacity - self.empty) / (self.full - self.empty);
+        ((fraction * LEVEL_BINS as f64).max(0.0) as usize).min(LEVEL_BINS - 1)
+    }
+
+    /// Steps from the last record with the rate per hour at each time and capacity until the battery
+    /// is empty or full or the horizon is reached, along with the interval from the spread of the rates
+    /// of the earlier sessions.
+    pub fn forecast<F: Fn(DateTime<Utc>, f64) -> f64>(
+        &self,
+        rate: F,
+        horizon: Duration,
+    ) -> Prediction {
+        let (predicted_data, time_to_target) = self.extend(&rate, 1.0, horizon);
+
+        // the faster and the slower rates seen in the earlier sessions, relative to their average
+        let ratios = self.rate_ratios();
+        let (slow, fast) = (
+            quantile(&ratios, (1.0 - self.options.confidence) / 2.0).min(1.0),
+            quantile(&ratios, (1.0 + self.options.confidence) / 2.0).max(1.0),
+        );
+        let (slow_data, slow_time) = self.extend(&rate, slow, horizon);
+        let (fast_data, fast_time) = self.extend(&rate, fast, horizon);
+
+        // the bounds last as long as the prediction, staying empty or full once they reach it
+        let end = [&predicted_data, &slow_data, &fast_data]
+            .iter()
+            .filter_map(|series| series.last())
+            .map(|record| record.date_time)
+            .max()
+            .unwrap();
+        let pad = |mut series: BatteryTimeSeries| {
+            let mut record = series.last().unwrap().clone();
+            if record.date_time < end {
+                record.date_time = end;
+                series.insert(record);
+            }
+            series
+        };
+        let (lower, upper) = match self.kind {
+            SessionKind::Charging => (pad(slow_data), pad(fast_data)),
+            _ => (pad(fast_data), pad(slow_data)),
+        };
+
+        Prediction {
+            kind: self.kind,
+            start: self.last.date_time,
+            rate: rate(self.last.date_time, self.last.capacity.value()),
+            time_to_empty: time_to_target.filter(|_| self.kind != SessionKind::Charging),
+            time_to_full: time_to_target.filter(|_| self.kind == SessionKind::Charging),
+            predicted_data,
+            interval: PredictionInterval {
+                confidence: self.options.confidence,
+                lower,
+                upper,
+                earliest: fast_time,
+                latest: slow_time,
+            },
+        }
+    }
+
+    /// The predicted records with the rate scaled by the factor, along with the time to empty or full if reached
+    fn extend<F: Fn(DateTime<Utc>, f64) -> f64>(
+        &self,
+        rate: &F,
+        factor: f64,
+        horizon: Duration,
+    ) -> (BatteryTimeSeries, Option<Duration>) {
+        let (target, expected_sign, state) = match self.kind {
+            SessionKind::Charging => (self.full, 1.0, ChargeState::Charging),
+            _ => (self.empty, -1.0, ChargeState::Discharging),
+        };
+        let unit = self.last.capacity.unit();
+
+        let mut predicted_data = BatteryTimeSeries::from_iter([self.last.clone()]);
+        let mut date_time = self.last.date_time;
+        let mut capacity = self.last.capacity.value();
+        let mut time_to_target = None;
+
+        // already empty or full
+        if (target - capacity) * expected_sign <= 0.0 {
+            time_to_target = Some(Duration::zero());
+
+            // a fully charged battery holds its capacity for the whole horizon
+            if self.is_fully_charged() {
+                predicted_data.insert(BatteryHistoryRecord {
+                    date_time: self.last.date_time + horizon,
+                    ..self.last.clone()
+                });
+            }
+        }
+
+        while time_to_target.is_none() && date_time - self.last.date_time < horizon {
+            let step_rate = rate(date_time, capacity) * factor;
+            let hours = self.options.step.num_seconds() as f64 / 3600.0;
+            let next_capacity = capacity + step_rate * hours;
+
+            let (next_date_time, next_capacity) = if (target - next_capacity) * expected_sign <= 0.0
+            {
+                // only the part of the step up to the target
+                let seconds = (target - capacity) / step_rate * 3600.0;
+                let next_date_time = date_time + Duration::seconds(seconds.round() as i64);
+                time_to_target = Some(next_date_time - self.last.date_time);
+                (next_date_time, target)
+            } else {
+                (date_time + self.options.step, next_capacity)
+            };
+
+            predicted_data.insert(BatteryHistoryRecord {
+                date_time: next_date_time,
+                capacity: Capacity::new(next_capacity, unit),
+                state,
+            });
+            date_time = next_date_time;
+            capacity = next_capacity;
+        }
+
+        (predicted_data, time_to_target)
+    }
+
+    /// The average rates of the earlier sessions divided by the mean of them, sorted
+    fn rate_ratios(&self) -> Vec<f64> {
+        let rates: Vec<f64> = self
+            .earlier
+            .iter()
+            .filter_map(|session| session.average_rate())
+            .filter(|rate| self.is_valid(*rate))
+            .collect();
+        if rates.is_empty() {
+            return Vec::new();
+        }
+
+        let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+        let mut ratios: Vec<f64> = rates.iter().map(|rate| rate / mean).collect();
+        ratios.sort_by(f64::total_cmp);
+        ratios
+    }
+}
+
+/// Change of the capacity and the time it took in each bin
+pub(crate) struct Sums<const N: usize> {
+    deltas: [f64; N],
+    hours: [f64; N],
+}
+
+impl<const N: usize> Sums<N> {
+    pub fn new() -> Self {
+        Sums {
+            deltas: [0.0; N],
+            hours: [0.0; N],
+        }
+    }
+
+    /// Adds the changes of the capacity between the consecutive records of the session
+    /// to the bins given by the time and the capacity at the start of each change
+    pub fn add_session<F: Fn(DateTime<Utc>, f64) -> usize>(&mut self, session: &Session, bin: F) {
+        let records: Vec<&BatteryHistoryRecord> = session.records.iter().collect();
+
+        for pair in records.windows(2) {
+            let hours = (pair[1].date_time - pair[0].date_time).num_seconds() as f64 / 3600.0;
+            if hours <= 0.0 {
+                continue;
+            }
+
+            let index = bin(pair[0].date_time, pair[0].capacity.value());
+            self.deltas[index] += pair[1].capacity.value() - pair[0].capacity.value();
+            self.hours[index] += hours;
+        }
+    }
+
+    /// Rate per hour of the bin, [None] when it has no data
+    pub fn rate(&self, bin: usize) -> Option<f64> {
+        if self.hours[bin] > 0.0 {
+            Some(self.deltas[bin] / self.hours[bin])
+        } else {
+            None
+        }
+    }
+
+    /// Rate per hour of all the bins together, [None] when there is no data
+    pub fn overall(&self) -> Option<f64> {
+        let hours: f64 = self.hours.iter().sum();
+        if hours > 0.0 {
+            Some(self.deltas.iter().sum::<f64>() / hours)
+        } else {
+            None
+        }
+    }
+
+    /// Rate of each bin divided by the overall rate, 1.0 for the bins without data
+    /// or when the rate of the bin is against the overall trend.
+    pub fn factors(&self) -> [f64; N] {
+        let mut factors = [1.0; N];
+        let overall = match self.overall() {
+            Some(overall) if overall != 0.0 => overall,
+            _ => return factors,
+        };
+
+        for (bin, factor) in factors.iter_mut().enumerate() {
+            if let Some(rate) = self.rate(bin) {
+                let ratio = rate / overall;
+                if ratio > 0.0 {
+                    *factor = ratio.clamp(MIN_FACTOR, MAX_FACTOR);
+                }
+            }
+        }
+
+        factors
+    }
+}
+
+/// Linearly interpolated quantile of the sorted values, 1.0 when there are none
+fn quantile(sorted: &[f64], probability: f64) -> f64 {
+    if sorted.is_empty() {
+        return 1.0;
+    }
+
+    let position = probability.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let (below, above) = (position.floor() as usize, position.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{hour, per_hour};
+
+    #[test]
+    fn discharging_at_the_recent_rate() {
+        // 1 mWh per hour
+        let data = per_hour(
+            &(0..=10)
+                .map(|i| (20.0 - i as f64, ChargeState::Discharging))
+                .collect::<Vec<_>>(),
+        );
+
+        let prediction = predict(&data).unwrap();
+
+        assert_eq!(prediction.kind, SessionKind::Discharging);
+        assert_eq!(prediction.start, hour(10));
+        assert!((prediction.rate + 1.0).abs() < 1e-9);
+        assert_eq!(prediction.time_to_empty, Some(Duration::hours(10)));
+        assert_eq!(prediction.empty_at(), Some(hour(20)));
+        assert_eq!(prediction.time_to_full, None);
+    }
+
+    #[test]
+    fn fully_charged_holds_the_capacity() {
+        let data = per_hour(&[
+            (90.0, ChargeState::Charging),
+            (95.0, ChargeState::Charging),
+            (98.0, ChargeState::FullyCharged),
+            (97.0, ChargeState::FullyCharged),
+        ]);
+
+        let prediction = predict(&data).unwrap();
+
+        assert_eq!(prediction.kind, SessionKind::Charging);
+        assert_eq!(prediction.rate, 0.0);
+        assert_eq!(prediction.time_to_full, Some(Duration::zero()));
+        assert_eq!(prediction.time_to_empty, None);
+
+        let horizon = PredictionOptions::default().max_horizon;
+        let predicted: Vec<(DateTime<Utc>, Capacity)> = prediction
+            .predicted_data
+            .iter()
+            .map(|record| (record.date_time, record.capacity))
+            .collect();
+        assert_eq!(
+            predicted,
+            [
+                (hour(3), Capacity::milliwatt_hours(97.0)),
+                (hour(3) + horizon, Capacity::milliwatt_hours(97.0))
+            ]
+        );
+        assert_eq!(
+            prediction.interval.lower.last().unwrap().capacity,
+            Capacity::milliwatt_hours(97.0)
+        );
+    }
+
+    #[test]
+    fn unknown_state_without_trend() {
+        let data = per_hour(&[(50.0, ChargeState::Unknown), (50.0, ChargeState::Unknown)]);
+
+        assert!(matches!(predict(&data), Err(BatteryError::Prediction(_))));
+
+        // the reported state alone is enough
+        let data = per_hour(&[
+            (50.0, ChargeState::Unknown),
+            (50.0, ChargeState::Discharging),
+        ]);
+        assert!(matches!(
+            predict(&data),
+            Err(BatteryError::Prediction(message)) if message.contains("discharging rate")
+        ));
+    }
+}
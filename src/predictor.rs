@@ -0,0 +1,638 @@
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::Timelike;
+use chrono::Utc;
+
+use crate::error::BatteryError;
+use crate::predict::History;
+use crate::predict::Prediction;
+use crate::predict::PredictionOptions;
+use crate::predict::Sums;
+use crate::predict::LEVEL_BINS;
+use crate::predict::MAX_FACTOR;
+use crate::predict::MIN_FACTOR;
+use crate::session::Session;
+use crate::time_series::BatteryTimeSeries;
+
+/// Model which forecasts the current session of a battery history.
+///
+/// The built-in ones are [ProfilePredictor], [LinearPredictor], [HoltPredictor], [SeasonalPredictor]
+/// and [NearestNeighbourPredictor]. A custom one only has to fill the [Prediction], e.g. with the
+/// predicted data starting at the last record of the history so that it is connected to the graph.
+pub trait Predictor {
+    /// Learns from the history, the forecast starts at its last record.
+    ///
+    /// # Returns
+    /// The error when the history can't be predicted from, e.g. when it is empty.
+    fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError>;
+
+    /// Forecasts the fitted history until the battery is empty or full, but at most for the horizon.
+    ///
+    /// # Returns
+    /// The [Prediction], or the error when the predictor hasn't been fitted.
+    fn forecast(&self, horizon: Duration) -> Result<Prediction, BatteryError>;
+}
+
+fn not_fitted() -> BatteryError {
+    BatteryError::Prediction("The predictor must be fitted before forecasting".to_string())
+}
+
+/// The rate of the current session, or of the earlier sessions when the current one is too short,
+/// zero for a fully charged battery
+fn base_rate(history: &History) -> Result<f64, BatteryError> {
+    if history.is_fully_charged() {
+        return Ok(0.0);
+    }
+
+    history
+        .recent_rate()
+        .or(history.earlier_rate())
+        .ok_or_else(|| history.no_rate_error())
+}
+
+/// Recent rate of the current session adjusted by the hour of the day and the capacity, see [crate::predict_with].
+#[derive(Debug, Clone, Default)]
+pub struct ProfilePredictor {
+    pub options: PredictionOptions,
+    fitted: Option<(History, f64, UsageProfile)>,
+}
+
+/// Average rate of the sessions by the hour of the day and by the capacity level,
+/// relative to their overall average rate.
+#[derive(Debug, Clone)]
+struct UsageProfile {
+    by_hour: [f64; 24],
+    by_level: [f64; LEVEL_BINS],
+}
+
+impl UsageProfile {
+    fn new(history: &History) -> Self {
+        let mut hours = Sums::<24>::new();
+        let mut levels = Sums::<LEVEL_BINS>::new();
+
+        for session in history.earlier.iter() {
+            hours.add_session(session, |date_time, _| date_time.hour() as usize);
+            levels.add_session(session, |_, capacity| history.level_bin(capacity));
+        }
+
+        UsageProfile {
+            by_hour: hours.factors(),
+            by_level: levels.factors(),
+        }
+    }
+
+    /// Factor by which the rate at the time and capacity differs from the rate at the reference
+    fn factor(
+        &self,
+        date_time: DateTime<Utc>,
+        level: usize,
+        reference: (DateTime<Utc>, usize),
+    ) -> f64 {
+        let hour =
+            self.by_hour[date_time.hour() as usize] / self.by_hour[reference.0.hour() as usize];
+        let level = self.by_level[level] / self.by_level[reference.1];
+
+        (hour * level).clamp(MIN_FACTOR, MAX_FACTOR)
+    }
+}
+
+impl ProfilePredictor {
+    pub fn new(options: PredictionOptions) -> Self {
+        ProfilePredictor {
+            options,
+            fitted: None,
+        }
+    }
+}
+
+impl Predictor for ProfilePredictor {
+    fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+        let history = History::new(history, &self.options)?;
+        let rate = base_rate(&history)?;
+        let profile = UsageProfile::new(&history);
+
+        self.fitted = Some((history, rate, profile));
+        Ok(())
+    }
+
+    fn forecast(&self, horizon: Duration) -> Result<Prediction, BatteryError> {
+        let (history, rate, profile) = self.fitted.as_ref().ok_or_else(not_fitted)?;
+        let reference = (
+            history.last.date_time,
+            history.level_bin(history.last.capacity.value()),
+        );
+
+        Ok(history.forecast(
+            |date_time, capacity| {
+                rate * profile.factor(date_time, history.level_bin(capacity), reference)
+            },
+            horizon,
+        ))
+    }
+}
+
+/// Extends the recent rate of the current session as it is.
+#[derive(Debug, Clone, Default)]
+pub struct LinearPredictor {
+    pub options: PredictionOptions,
+    fitted: Option<(History, f64)>,
+}
+
+impl LinearPredictor {
+    pub fn new(options: PredictionOptions) -> Self {
+        LinearPredictor {
+            options,
+            fitted: None,
+        }
+    }
+}
+
+impl Predictor for LinearPredictor {
+    fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+        let history = History::new(history, &self.options)?;
+        let rate = base_rate(&history)?;
+
+        self.fitted = Some((history, rate));
+        Ok(())
+    }
+
+    fn forecast(&self, horizon: Duration) -> Result<Prediction, BatteryError> {
+        let (history, rate) = self.fitted.as_ref().ok_or_else(not_fitted)?;
+
+        Ok(history.forecast(|_, _| *rate, horizon))
+    }
+}
+
+/// Double exponential smoothing of the current session resampled at the step of the options,
+/// the smoothed trend is extended as the rate.
+#[derive(Debug, Clone)]
+pub struct HoltPredictor {
+    pub options: PredictionOptions,
+    /// smoothing of the level between 0 and 1, the higher the more it follows the latest capacities
+    pub alpha: f64,
+    /// smoothing of the trend between 0 and 1, the higher the more it follows the latest changes
+    pub beta: f64,
+    fitted: Option<(History, f64)>,
+}
+
+impl Default for HoltPredictor {
+    fn default() -> Self {
+        HoltPredictor::new(PredictionOptions::default())
+    }
+}
+
+impl HoltPredictor {
+    pub fn new(options: PredictionOptions) -> Self {
+        HoltPredictor {
+            options,
+            alpha: 0.5,
+            beta: 0.05,
+            fitted: None,
+        }
+    }
+
+    /// The smoothed trend per hour of the current session
+    fn trend(&self, history: &History) -> Option<f64> {
+        let records = &history.current.records;
+        let (first, last) = (records.first()?, records.last()?);
+        let step_hours = self.options.step.num_seconds() as f64 / 3600.0;
+
+        let capacity_at = |date_time: DateTime<Utc>| {
+            let before = records.at_or_before(date_time)?;
+            let after = records.at_or_after(date_time)?;
+            if after.date_time == before.date_time {
+                return Some(before.capacity.value());
+            }
+
+            let fraction = (date_time - before.date_time).num_seconds() as f64
+                / (after.date_time - before.date_time).num_seconds() as f64;
+            Some(
+                before.capacity.value()
+                    + (after.capacity.value() - before.capacity.value()) * fraction,
+            )
+        };
+
+        let mut date_time = first.date_time + self.options.step;
+        let mut level = first.capacity.value();
+        let mut trend = capacity_at(date_time)? - level;
+        while date_time <= last.date_time {
+            let capacity = capacity_at(date_time)?;
+            let previous_level = level;
+
+            level = self.alpha * capacity + (1.0 - self.alpha) * (level + trend);
+            trend = self.beta * (level - previous_level) + (1.0 - self.beta) * trend;
+            date_time += self.options.step;
+        }
+
+        Some(trend / step_hours)
+    }
+}
+
+impl Predictor for HoltPredictor {
+    fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+        if !(0.0..=1.0).contains(&self.alpha) || !(0.0..=1.0).contains(&self.beta) {
+            return Err(BatteryError::InvalidOption(format!(
+                "The smoothing factors must be between 0 and 1, found {} and {}",
+                self.alpha, self.beta
+            )));
+        }
+
+        let history = History::new(history, &self.options)?;
+        let rate = match self.trend(&history).filter(|rate| history.is_valid(*rate)) {
+            Some(rate) => rate,
+            None => base_rate(&history)?,
+        };
+
+        self.fitted = Some((history, rate));
+        Ok(())
+    }
+
+    fn forecast(&self, horizon: Duration) -> Result<Prediction, BatteryError> {
+        let (history, rate) = self.fitted.as_ref().ok_or_else(not_fitted)?;
+
+        Ok(history.forecast(|_, _| *rate, horizon))
+    }
+}
+
+/// Average rate of the earlier sessions by the hour of the week, or by the hour of the day for the hours
+/// of the week without data, irrespective of the current session. The times are in UTC.
+#[derive(Debug, Clone, Default)]
+pub struct SeasonalPredictor {
+    pub options: PredictionOptions,
+    fitted: Option<(History, Vec<f64>)>,
+}
+
+impl SeasonalPredictor {
+    pub fn new(options: PredictionOptions) -> Self {
+        SeasonalPredictor {
+            options,
+            fitted: None,
+        }
+    }
+}
+
+fn hour_of_week(date_time: DateTime<Utc>) -> usize {
+    date_time.weekday().num_days_from_monday() as usize * 24 + date_time.hour() as usize
+}
+
+impl Predictor for SeasonalPredictor {
+    fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+        let history = History::new(history, &self.options)?;
+
+        // a fully charged battery holds its capacity whatever the earlier sessions did
+        if history.is_fully_charged() {
+            let rate = base_rate(&history)?;
+            self.fitted = Some((history, vec![rate; 168]));
+            return Ok(());
+        }
+
+        let mut by_week = Sums::<168>::new();
+        let mut by_day = Sums::<24>::new();
+        for session in history.earlier.iter() {
+            by_week.add_session(session, |date_time, _| hour_of_week(date_time));
+            by_day.add_session(session, |date_time, _| date_time.hour() as usize);
+        }
+
+        // without earlier sessions there is no profile, so it is the same as the linear prediction
+        let fallback = by_day
+            .overall()
+            .filter(|rate| history.is_valid(*rate))
+            .map_or_else(|| base_rate(&history), Ok)?;
+
+        let rates = (0..168)
+            .map(|hour| {
+                by_week
+                    .rate(hour)
+                    .filter(|rate| history.is_valid(*rate))
+                    .or(by_day
+                        .rate(hour % 24)
+                        .filter(|rate| history.is_valid(*rate)))
+                    .unwrap_or(fallback)
+            })
+            .collect();
+
+        self.fitted = Some((history, rates));
+        Ok(())
+    }
+
+    fn forecast(&self, horizon: Duration) -> Result<Prediction, BatteryError> {
+        let (history, rates) = self.fitted.as_ref().ok_or_else(not_fitted)?;
+
+        Ok(history.forecast(|date_time, _| rates[hour_of_week(date_time)], horizon))
+    }
+}
+
+/// Finds the earlier sessions most similar to the current one, by the hour and the capacity they started at
+/// and their rate over the same duration as the current session so far, and follows their rates
+/// at each capacity level.
+#[derive(Debug, Clone)]
+pub struct NearestNeighbourPredictor {
+    pub options: PredictionOptions,
+    /// number of the earlier sessions to follow
+    pub neighbours: usize,
+    fitted: Option<(History, [f64; LEVEL_BINS])>,
+}
+
+impl Default for NearestNeighbourPredictor {
+    fn default() -> Self {
+        NearestNeighbourPredictor::new(PredictionOptions::default())
+    }
+}
+
+impl NearestNeighbourPredictor {
+    pub fn new(options: PredictionOptions) -> Self {
+        NearestNeighbourPredictor {
+            options,
+            neighbours: 3,
+            fitted: None,
+        }
+    }
+
+    /// Distance of the earlier session from the current one
+    fn distance(history: &History, session: &Session, typical_rate: f64) -> f64 {
+        let current = &history.current;
+
+        let hours_apart = (current.start.hour() as f64 - session.start.hour() as f64).abs();
+        let hour = hours_apart.min(24.0 - hours_apart) / 12.0;
+
+        let range = (history.full - history.empty).abs().max(f64::EPSILON);
+        let level = (current.start_capacity.value() - session.start_capacity.value()).abs() / range;
+
+        // the rate over the beginning of the session, as long as the current session so far
+        let beginning = session.records.slice(..=session.start + current.duration());
+        let rate = match (current.average_rate(), beginning.first(), beginning.last()) {
+            (Some(current_rate), Some(first), Some(last)) if last.date_time > first.date_time => {
+                let rate = (last.capacity.value() - first.capacity.value())
+                    / ((last.date_time - first.date_time).num_seconds() as f64 / 3600.0);
+                // the typical rate is zero for a fully charged battery
+                ((current_rate - rate) / typical_rate.abs().max(f64::EPSILON)).abs()
+            }
+            _ => 0.0,
+        };
+
+        hour + level + rate
+    }
+}
+
+impl Predictor for NearestNeighbourPredictor {
+    fn fit(&mut self, history: &BatteryTimeSeries) -> Result<(), BatteryError> {
+        if self.neighbours == 0 {
+            return Err(BatteryError::InvalidOption(
+                "At least one neighbour must be followed".to_string(),
+            ));
+        }
+
+        let history = History::new(history, &self.options)?;
+        let fallback = base_rate(&history)?;
+
+        // a fully charged battery holds its capacity whatever the earlier sessions did
+        if history.is_fully_charged() {
+            self.fitted = Some((history, [fallback; LEVEL_BINS]));
+            return Ok(());
+        }
+
+        let mut sessions: Vec<(f64, &Session)> = history
+            .earlier
+            .iter()
+            .filter(|session| session.sample_count() > 1)
+            .map(|session| (Self::distance(&history, session, fallback), session))
+            .collect();
+        sessions.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // the average of the rates of the neighbours at each level, their average rate when they
+        // didn't reach the level
+        let mut rates = [fallback; LEVEL_BINS];
+        let neighbours: Vec<Sums<LEVEL_BINS>> = sessions
+            .iter()
+            .take(self.neighbours)
+            .map(|(_, session)| {
+                let mut levels = Sums::<LEVEL_BINS>::new();
+                levels.add_session(session, |_, capacity| history.level_bin(capacity));
+                levels
+            })
+            .collect();
+
+        if !neighbours.is_empty() {
+            for (level, rate) in rates.iter_mut().enumerate() {
+                let level_rates: Vec<f64> = neighbours
+                    .iter()
+                    .filter_map(|levels| levels.rate(level).or(levels.overall()))
+                    .filter(|rate| history.is_valid(*rate))
+                    .collect();
+                if !level_rates.is_empty() {
+                    *rate = level_rates.iter().sum::<f64>() / level_rates.len() as f64;
+                }
+            }
+        }
+
+        self.fitted = Some((history, rates));
+        Ok(())
+    }
+
+    fn forecast(&self, horizon: Duration) -> Result<Prediction, BatteryError> {
+        let (history, rates) = self.fitted.as_ref().ok_or_else(not_fitted)?;
+
+        Ok(history.forecast(|_, capacity| rates[history.level_bin(capacity)], horizon))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_data::BatteryHistoryRecord;
+    use crate::read_data::ChargeState;
+    use crate::session::SessionKind;
+    use crate::test_util::{hour, per_hour};
+    use crate::units::Capacity;
+
+    /// The capacities in mWh at the hours, the sessions follow the trend of the capacity
+    fn at_hours(records: &[(i64, f64)]) -> BatteryTimeSeries {
+        records
+            .iter()
+            .map(|(hours, capacity)| BatteryHistoryRecord {
+                date_time: hour(*hours),
+                capacity: Capacity::milliwatt_hours(*capacity),
+                state: ChargeState::Unknown,
+            })
+            .collect()
+    }
+
+    fn fit_and_forecast<P: Predictor>(mut predictor: P, history: &BatteryTimeSeries) -> Prediction {
+        predictor.fit(history).unwrap();
+        predictor.forecast(Duration::days(2)).unwrap()
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-6,
+            "Expected {expected}, found {value}"
+        );
+    }
+
+    /// Discharging by 1 mWh per hour from 20 mWh for 10 hours
+    fn constant_rate() -> BatteryTimeSeries {
+        per_hour(
+            &(0..=10)
+                .map(|i| (20.0 - i as f64, ChargeState::Discharging))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn linear_time_to_empty() {
+        let prediction = fit_and_forecast(LinearPredictor::default(), &constant_rate());
+
+        assert_eq!(prediction.kind, SessionKind::Discharging);
+        assert_close(prediction.rate, -1.0);
+        assert_eq!(prediction.time_to_empty, Some(Duration::hours(10)));
+        assert_eq!(prediction.empty_at(), Some(hour(20)));
+    }
+
+    #[test]
+    fn holt_converges_to_the_trend() {
+        // 5 mWh in the first hour of the session, then 1 mWh per hour
+        let mut records = vec![(0, 31.0), (1, 30.0), (2, 25.0)];
+        records.extend((3..=16).map(|i| (i, 27.0 - i as f64)));
+
+        let prediction = fit_and_forecast(HoltPredictor::default(), &at_hours(&records));
+
+        assert!(
+            (prediction.rate + 1.0).abs() < 1e-3,
+            "Expected -1, found {}",
+            prediction.rate
+        );
+    }
+
+    #[test]
+    fn holt_smoothing_factors_between_zero_and_one() {
+        for (alpha, beta) in [(1.5, 0.05), (0.5, -0.1)] {
+            let mut predictor = HoltPredictor {
+                alpha,
+                beta,
+                ..HoltPredictor::default()
+            };
+
+            assert!(matches!(
+                predictor.fit(&constant_rate()),
+                Err(BatteryError::InvalidOption(_))
+            ));
+        }
+    }
+
+    /// Discharging sessions on Thursday 09:00 at 8 mWh per hour, and on Friday 08:00, 09:00 and 10:00
+    /// at 1, 4 and 2 mWh per hour, then the current one from Friday 12:00 until the last record
+    fn weekly(last_hour: i64) -> BatteryTimeSeries {
+        at_hours(&[
+            (-24, 100.0),
+            (-23, 99.0),
+            (-22, 91.0),
+            (-21, 100.0),
+            (0, 100.0),
+            (1, 99.0),
+            (2, 95.0),
+            (3, 93.0),
+            (4, 100.0),
+            (last_hour, 99.0),
+        ])
+    }
+
+    #[test]
+    fn seasonal_rate_of_the_hour_of_the_week() {
+        // Friday 09:00 a week later
+        let prediction = fit_and_forecast(SeasonalPredictor::default(), &weekly(169));
+        assert_close(prediction.rate, -4.0);
+
+        // Saturday 09:00, only the hour of the day has data
+        let prediction = fit_and_forecast(SeasonalPredictor::default(), &weekly(193));
+        assert_close(prediction.rate, -6.0);
+
+        // Friday 15:00, neither has data
+        let prediction = fit_and_forecast(SeasonalPredictor::default(), &weekly(175));
+        assert_close(prediction.rate, -15.0 / 4.0);
+    }
+
+    /// Discharging sessions from 100 mWh at 08:00 by 2 mWh per hour and at 20:00 by 10 mWh per hour,
+    /// then the current one from 100 mWh at 08:00 the next day by 2 mWh per hour
+    fn two_sessions() -> BatteryTimeSeries {
+        at_hours(&[
+            (-2, 90.0),
+            (-1, 95.0),
+            (0, 100.0),
+            (1, 98.0),
+            (2, 96.0),
+            (3, 94.0),
+            (4, 92.0),
+            (5, 96.0),
+            (6, 100.0),
+            (12, 100.0),
+            (13, 90.0),
+            (14, 80.0),
+            (15, 70.0),
+            (16, 85.0),
+            (17, 100.0),
+            (24, 100.0),
+            (25, 98.0),
+            (26, 96.0),
+        ])
+    }
+
+    #[test]
+    fn nearest_neighbour_follows_the_closest_session() {
+        let predictor = |neighbours| NearestNeighbourPredictor {
+            neighbours,
+            ..NearestNeighbourPredictor::default()
+        };
+
+        let prediction = fit_and_forecast(predictor(1), &two_sessions());
+        assert_close(prediction.rate, -2.0);
+
+        // along with the session in the evening
+        let prediction = fit_and_forecast(predictor(2), &two_sessions());
+        assert_close(prediction.rate, -6.0);
+
+        assert!(matches!(
+            predictor(0).fit(&two_sessions()),
+            Err(BatteryError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn fully_charged_battery_is_held() {
+        let mut history = two_sessions();
+        history.insert(BatteryHistoryRecord {
+            date_time: hour(27),
+            capacity: Capacity::milliwatt_hours(100.0),
+            state: ChargeState::FullyCharged,
+        });
+
+        for prediction in [
+            fit_and_forecast(SeasonalPredictor::default(), &history),
+            fit_and_forecast(NearestNeighbourPredictor::default(), &history),
+        ] {
+            assert_eq!(prediction.rate, 0.0);
+            assert!(prediction
+                .predicted_data
+                .iter()
+                .all(|record| record.capacity == Capacity::milliwatt_hours(100.0)));
+        }
+    }
+
+    #[test]
+    fn forecast_before_fit() {
+        let predictors: [Box<dyn Predictor>; 5] = [
+            Box::<ProfilePredictor>::default(),
+            Box::<LinearPredictor>::default(),
+            Box::<HoltPredictor>::default(),
+            Box::<SeasonalPredictor>::default(),
+            Box::<NearestNeighbourPredictor>::default(),
+        ];
+
+        for predictor in predictors.iter() {
+            assert!(matches!(
+                predictor.forecast(Duration::hours(1)),
+                Err(error) if error.to_string() == not_fitted().to_string()
+            ));
+        }
+    }
+}
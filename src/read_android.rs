@@ -0,0 +1,292 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use std::fs;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::read_html::local_to_utc;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+/// Power source reported by the `plug=` field of batterystats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlugType {
+    None,
+    Ac,
+    Usb,
+    Wireless,
+    Dock,
+    Unknown,
+}
+
+/// Single event of the batterystats history, the fields which aren't present in the
+/// event are carried over from the previous events.
+#[derive(Debug, Clone)]
+pub struct BatteryStatsEntry {
+    pub date_time: DateTime<Utc>,
+    /// battery level in percentage
+    pub level: i32,
+    pub plug: PlugType,
+    pub state: ChargeState,
+    /// voltage in mV
+    pub voltage: Option<i32>,
+    /// temperature in °C
+    pub temperature: Option<f32>,
+}
+
+/// Reads the saved output of `dumpsys batterystats --history` from given path.
+///
+/// The times in the history are in the local time of the phone,
+/// they are converted to [Utc] assuming that it is the same as the local time of this machine.
+pub fn get_batterystats_history(path: &str) -> Result<Vec<BatteryStatsEntry>, BatteryError> {
+    parse_batterystats_history(&fs::read_to_string(path)?)
+}
+
+/// Same as [get_batterystats_history] but in the shape returned by [crate::read_data::get_data],
+/// the capacity of the records is the percentage.
+pub fn get_batterystats_data(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut series = BatteryTimeSeries::new();
+
+    for entry in get_batterystats_history(path)? {
+        series.insert(BatteryHistoryRecord {
+            date_time: entry.date_time,
+            capacity: Capacity::percent(entry.level as f64),
+            state: entry.state,
+        });
+    }
+
+    Ok(series)
+}
+
+pub fn parse_batterystats_history(
+    contents: &str,
+) -> Result<Vec<BatteryStatsEntry>, BatteryError> {
+    let mut entries = Vec::new();
+
+    // the offsets in the history are relative to this time
+    let mut base_time: Option<DateTime<Utc>> = None;
+
+    let mut plug = PlugType::Unknown;
+    let mut status = String::new();
+    let mut voltage = None;
+    let mut temperature = None;
+
+    for (i, line) in contents.lines().enumerate() {
+        // e.g. "      +1m02s345ms (2) 099 status=discharging plug=none temp=260 volt=4200"
+        let mut tokens = line.split_whitespace();
+        let offset = match tokens.next().and_then(parse_offset) {
+            Some(offset) => offset,
+            None => continue,
+        };
+        match tokens.next() {
+            Some(token) if token.starts_with('(') && token.ends_with(')') => {}
+            _ => continue,
+        }
+
+        let tokens: Vec<&str> = tokens.collect();
+
+        // e.g. "0 (15) RESET:TIME: 2023-10-20-08-03-21" or "+5m (3) TIME: 2023-10-20-09-00-00"
+        if let Some(index) = tokens.iter().position(|token| token.ends_with("TIME:")) {
+            let time = tokens
+                .get(index + 1)
+                .and_then(|time| NaiveDateTime::parse_from_str(time, "%Y-%m-%d-%H-%M-%S").ok())
+                .and_then(local_to_utc)
+                .ok_or_else(|| BatteryError::Parse {
+                    line: Some(i as u64 + 1),
+                    message: format!("Invalid TIME in {line}"),
+                })?;
+            base_time = Some(time - offset);
+            continue;
+        }
+
+        let level = match tokens.first().filter(|token| token.len() == 3) {
+            Some(level) => match level.parse::<i32>() {
+                Ok(level) => level,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+
+        for token in tokens.iter().skip(1) {
+            if let Some(value) = token.strip_prefix("plug=") {
+                plug = match value {
+                    "none" => PlugType::None,
+                    "ac" => PlugType::Ac,
+                    "usb" => PlugType::Usb,
+                    "wireless" => PlugType::Wireless,
+                    "dock" => PlugType::Dock,
+                    _ => PlugType::Unknown,
+                };
+            } else if let Some(value) = token.strip_prefix("status=") {
+                status = value.to_string();
+            } else if let Some(value) = token.strip_prefix("volt=") {
+                voltage = value.parse().ok();
+            } else if let Some(value) = token.strip_prefix("temp=") {
+                temperature = value.parse::<f32>().ok().map(|temp| temp / 10.0);
+            } else if *token == "-plugged" {
+                plug = PlugType::None;
+            } else if *token == "+plugged" && plug == PlugType::None {
+                plug = PlugType::Unknown;
+            }
+        }
+
+        let base_time = base_time.ok_or_else(|| BatteryError::Parse {
+            line: Some(i as u64 + 1),
+            message: "The history doesn't start with RESET:TIME".to_string(),
+        })?;
+
+        entries.push(BatteryStatsEntry {
+            date_time: base_time + offset,
+            level,
+            plug,
+            state: match (plug, status.as_str()) {
+                (PlugType::None, _) => ChargeState::Discharging,
+                (_, "full") => ChargeState::FullyCharged,
+                (_, "not-charging") => ChargeState::PendingCharge,
+                (PlugType::Unknown, "discharging") => ChargeState::Discharging,
+                (PlugType::Unknown, "") => ChargeState::Unknown,
+                _ => ChargeState::Charging,
+            },
+            voltage,
+            temperature,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Parses offsets like "0", "+1s002ms" or "+1d02h03m04s005ms"
+fn parse_offset(text: &str) -> Option<Duration> {
+    if text == "0" {
+        return Some(Duration::zero());
+    }
+
+    let mut rest = text.strip_prefix('+')?;
+    let mut offset = Duration::zero();
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        offset = offset
+            + match &rest[..unit] {
+                "d" => Duration::days(value),
+                "h" => Duration::hours(value),
+                "m" => Duration::minutes(value),
+                "s" => Duration::seconds(value),
+                "ms" => Duration::milliseconds(value),
+                _ => return None,
+            };
+        rest = &rest[unit..];
+    }
+
+    Some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: &str = "\
+Battery History (3% used, 10KB used of 4096KB, 45 strings using 3KB):
+                    0 (15) RESET:TIME: 2023-10-20-08-03-21
+                    0 (2) 100 status=full health=good plug=ac temp=260 volt=4350
+           +1m02s003ms (2) 099 status=discharging -plugged volt=4200
+        +1h00m00s000ms (2) 090
+        +1h05m00s000ms (2) 091 status=charging +plugged
+        +1h05m30s000ms (2) 092 plug=usb temp=280
+        +2h00m00s000ms (3) TIME: 2023-10-20-10-30-00
+        +2h00m01s000ms (2) 093
+";
+
+    /// The local time of the phone, the same as the local time of this machine
+    fn local(date_time: &str) -> DateTime<Utc> {
+        local_to_utc(NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d %H:%M:%S%.f").unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn offsets_from_the_reset_time() {
+        let entries = parse_batterystats_history(HISTORY).unwrap();
+
+        let times: Vec<(DateTime<Utc>, i32)> = entries
+            .iter()
+            .map(|entry| (entry.date_time, entry.level))
+            .collect();
+        assert_eq!(
+            times,
+            [
+                (local("2023-10-20 08:03:21"), 100),
+                (local("2023-10-20 08:04:23.003"), 99),
+                (local("2023-10-20 09:03:21"), 90),
+                (local("2023-10-20 09:08:21"), 91),
+                (local("2023-10-20 09:08:51"), 92),
+                // the clock was set forward by 26 minutes 39 seconds
+                (local("2023-10-20 10:30:01"), 93),
+            ]
+        );
+    }
+
+    #[test]
+    fn plug_and_state_carried_over() {
+        let entries = parse_batterystats_history(HISTORY).unwrap();
+
+        let states: Vec<(PlugType, ChargeState)> = entries
+            .iter()
+            .map(|entry| (entry.plug, entry.state))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (PlugType::Ac, ChargeState::FullyCharged),
+                (PlugType::None, ChargeState::Discharging),
+                (PlugType::None, ChargeState::Discharging),
+                (PlugType::Unknown, ChargeState::Charging),
+                (PlugType::Usb, ChargeState::Charging),
+                (PlugType::Usb, ChargeState::Charging),
+            ]
+        );
+
+        assert_eq!(entries[2].voltage, Some(4200));
+        assert_eq!(entries[2].temperature, Some(26.0));
+        assert_eq!(entries[4].temperature, Some(28.0));
+    }
+
+    #[test]
+    fn history_without_reset_time() {
+        let history = "Battery History:\n  +1s000ms (2) 099 status=discharging plug=none\n";
+
+        assert!(matches!(
+            parse_batterystats_history(history),
+            Err(BatteryError::Parse { line: Some(2), .. })
+        ));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_offset("0"), Some(Duration::zero()));
+        assert_eq!(
+            parse_offset("+1m02s003ms"),
+            Some(Duration::milliseconds(62003))
+        );
+        assert_eq!(
+            parse_offset("+1d02h03m04s005ms"),
+            Some(
+                Duration::days(1)
+                    + Duration::hours(2)
+                    + Duration::minutes(3)
+                    + Duration::seconds(4)
+                    + Duration::milliseconds(5)
+            )
+        );
+        assert_eq!(parse_offset("+1x"), None);
+        assert_eq!(parse_offset("1s"), None);
+    }
+}
@@ -1,15 +1,21 @@
-use chrono::serde::ts_seconds::deserialize as ts_s;
+use chrono::serde::ts_seconds;
 use chrono::DateTime;
 use chrono::Utc;
 use csv::ReaderBuilder;
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::error::Error;
+use serde::Serialize;
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+use crate::error::BatteryError;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::as_milliwatt_hours;
+use crate::units::Capacity;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ChargeState {
     Charging,
     Discharging,
+    FullyCharged,
+    PendingCharge,
     Unknown,
 }
 
@@ -23,18 +29,20 @@ pub struct BatteryLogRecord {
     pub state: i32,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BatteryHistoryRecord {
-    #[serde(deserialize_with = "ts_s")]
+    #[serde(with = "ts_seconds")]
     pub date_time: DateTime<Utc>,
-    pub capacity: i32,
+    /// the csv holds the capacity in mWh, the other sources keep their own unit
+    #[serde(with = "as_milliwatt_hours")]
+    pub capacity: Capacity,
     pub state: ChargeState,
 }
 
 impl From<BatteryLogRecord> for BatteryHistoryRecord {
     fn from(log_record: BatteryLogRecord) -> Self {
         BatteryHistoryRecord {
-            capacity: (log_record.capacity / 3.6) as i32,
+            capacity: Capacity::joules(log_record.capacity as f64),
             date_time: log_record.date_time,
             state: match log_record.state {
                 -1 => ChargeState::Discharging,
@@ -54,61 +62,26 @@ impl From<BatteryLogRecord> for BatteryHistoryRecord {
 /// The csv in the provided path must contain header corresponding to data types in [BatteryHistoryRecord] otherwise deserialization will panic
 ///
 /// # Returns
-/// The unsorted [HashMap] which contains [`DateTime<Utc>`] key and [BatteryHistoryRecord] value pairs.
-pub fn get_data(
-    path: &str,
-) -> Result<HashMap<DateTime<Utc>, BatteryHistoryRecord>, Box<dyn Error>> {
-    let mut data_hash_map: HashMap<DateTime<Utc>, BatteryHistoryRecord> = HashMap::new();
+/// The [BatteryTimeSeries] of [BatteryHistoryRecord]s ordered by their date_time.
+pub fn get_data(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut series = BatteryTimeSeries::new();
 
     let mut rdr = ReaderBuilder::new().has_headers(true).from_path(path)?;
     for result in rdr.deserialize::<BatteryHistoryRecord>() {
-        let record = result?;
-        data_hash_map.insert(record.date_time, record);
+        series.insert(result?);
     }
 
-    Ok(data_hash_map)
+    Ok(series)
 }
 
-pub fn get_log(path: &str) -> Result<HashMap<DateTime<Utc>, BatteryHistoryRecord>, Box<dyn Error>> {
-    let mut data_hash_map: HashMap<DateTime<Utc>, BatteryHistoryRecord> = HashMap::new();
+pub fn get_log(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut series = BatteryTimeSeries::new();
 
     let mut rdr = ReaderBuilder::new().has_headers(true).from_path(path)?;
     for result in rdr.deserialize::<BatteryLogRecord>() {
-        let record = result?;
-        data_hash_map.insert(record.date_time, record.into());
-    }
-
-    Ok(data_hash_map)
-}
-
-/// Stores sorted values into x_data, y_data
-pub fn sort_hashmap(
-    data: &HashMap<DateTime<Utc>, BatteryHistoryRecord>,
-    x_data: &mut Vec<DateTime<Utc>>,
-    y_data: &mut Vec<i32>,
-) {
-    for (key, _) in data.iter() {
-        x_data.push(*key);
-    }
-
-    // since hashmap isn't ordered, ordering x_data
-    x_data.sort();
-
-    let mut invalid_values: Vec<usize> = vec![];
-
-    // and selecting y_data based on that data
-    for (i, dat) in (0..).zip(x_data.iter()) {
-        if let Some(val) = data.get(dat) {
-            if *dat == val.date_time {
-                y_data.push(val.capacity);
-            } else {
-                invalid_values.push(i);
-            }
-        }
+        let record: BatteryHistoryRecord = result?.into();
+        series.insert(record);
     }
 
-    let _: Vec<chrono::DateTime<chrono::Utc>> = invalid_values
-        .iter()
-        .map(|index| x_data.remove(*index))
-        .collect();
+    Ok(series)
 }
@@ -0,0 +1,572 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Local;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use scraper::ElementRef;
+use scraper::Html;
+use scraper::Selector;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+/// Power state of the machine as reported in the "Recent usage" and "Battery usage" tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    ConnectedStandby,
+    Suspended,
+    ReportGenerated,
+    Unknown,
+}
+
+/// Source of power as reported in the "Recent usage" table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerSource {
+    Ac,
+    Battery,
+    Unknown,
+}
+
+/// Period covered by a row of the "Usage history", "Battery capacity history"
+/// and "Battery life estimates" tables. Single day rows have the same start and end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportPeriod {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecentUsageRecord {
+    pub date_time: DateTime<Utc>,
+    pub state: PowerState,
+    pub source: PowerSource,
+    pub percentage: Option<i32>,
+    pub capacity: Option<Capacity>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatteryUsageRecord {
+    pub date_time: DateTime<Utc>,
+    pub state: PowerState,
+    pub duration: Option<Duration>,
+    pub energy_drained_percentage: Option<i32>,
+    pub energy_drained: Option<Capacity>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UsageHistoryRecord {
+    pub period: ReportPeriod,
+    pub battery_active: Option<Duration>,
+    pub battery_connected_standby: Option<Duration>,
+    pub ac_active: Option<Duration>,
+    pub ac_connected_standby: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CapacityHistoryRecord {
+    pub period: ReportPeriod,
+    pub full_charge_capacity: Capacity,
+    pub design_capacity: Capacity,
+}
+
+#[derive(Debug, Clone)]
+pub struct LifeEstimateRecord {
+    /// [None] for the "Since OS install" row
+    pub period: Option<ReportPeriod>,
+    pub full_charge_active: Option<Duration>,
+    pub full_charge_connected_standby: Option<Duration>,
+    pub design_capacity_active: Option<Duration>,
+    pub design_capacity_connected_standby: Option<Duration>,
+}
+
+/// All the tables of the html battery report which are of interest to us.
+///
+/// `history` holds the "Recent usage" rows which have a remaining capacity, in the same
+/// shape as returned by [crate::read_data::get_data].
+#[derive(Debug, Clone, Default)]
+pub struct BatteryReport {
+    pub history: BatteryTimeSeries,
+    pub recent_usage: Vec<RecentUsageRecord>,
+    pub battery_usage: Vec<BatteryUsageRecord>,
+    pub usage_history: Vec<UsageHistoryRecord>,
+    pub capacity_history: Vec<CapacityHistoryRecord>,
+    pub life_estimates: Vec<LifeEstimateRecord>,
+}
+
+/// Reads the html generated by `powercfg /batteryreport` from given path.
+///
+/// # Parameters
+/// path: [&str] of the battery report html
+///
+/// The times in the report are in the local time of the machine which generated it,
+/// they are converted to [Utc] assuming that it is the same as the local time of this machine.
+///
+/// # Returns
+/// The [BatteryReport] consisting of all the recognized tables, tables which are
+/// missing from the report are left empty.
+pub fn get_html_report(path: &str) -> Result<BatteryReport, BatteryError> {
+    let contents = std::fs::read_to_string(path)?;
+    parse_html_report(&contents)
+}
+
+/// Same as [get_html_report] but only returns the history of remaining capacities.
+pub fn get_html_data(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    Ok(get_html_report(path)?.history)
+}
+
+pub fn parse_html_report(contents: &str) -> Result<BatteryReport, BatteryError> {
+    let document = Html::parse_document(contents);
+    let heading_selector = selector("h2")?;
+
+    let mut report = BatteryReport::default();
+
+    for heading in document.select(&heading_selector) {
+        let title = cell_text(&heading).to_lowercase();
+
+        let table = match next_table(&heading) {
+            Some(table) => table,
+            None => continue,
+        };
+
+        match title.as_str() {
+            "recent usage" => report.recent_usage = parse_recent_usage(&table)?,
+            "battery usage" => report.battery_usage = parse_battery_usage(&table)?,
+            "usage history" => report.usage_history = parse_usage_history(&table)?,
+            "battery capacity history" => report.capacity_history = parse_capacity_history(&table)?,
+            "battery life estimates" => report.life_estimates = parse_life_estimates(&table)?,
+            _ => {}
+        }
+    }
+
+    for usage in report.recent_usage.iter() {
+        if let Some(capacity) = usage.capacity {
+            report.history.insert(BatteryHistoryRecord {
+                date_time: usage.date_time,
+                capacity,
+                state: match usage.source {
+                    PowerSource::Ac => ChargeState::Charging,
+                    PowerSource::Battery => ChargeState::Discharging,
+                    PowerSource::Unknown => ChargeState::Unknown,
+                },
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+fn parse_recent_usage(table: &ElementRef) -> Result<Vec<RecentUsageRecord>, BatteryError> {
+    let mut records = Vec::new();
+    let mut current_date = None;
+
+    for row in table_rows(table)? {
+        let cells = row_cells(&row);
+        let date_time = match row_date_time(cells.first(), &mut current_date)? {
+            Some(date_time) => date_time,
+            None => continue,
+        };
+
+        // start time, state, source, percentage, capacity
+        records.push(RecentUsageRecord {
+            date_time,
+            state: parse_power_state(&nth_text(&cells, 1)),
+            source: match nth_text(&cells, 2).to_lowercase().as_str() {
+                "ac" => PowerSource::Ac,
+                "battery" => PowerSource::Battery,
+                _ => PowerSource::Unknown,
+            },
+            percentage: parse_percentage(&nth_text(&cells, 3)),
+            capacity: parse_number(&nth_text(&cells, 4)).map(Capacity::milliwatt_hours),
+        });
+    }
+
+    Ok(records)
+}
+
+fn parse_battery_usage(table: &ElementRef) -> Result<Vec<BatteryUsageRecord>, BatteryError> {
+    let mut records = Vec::new();
+    let mut current_date = None;
+
+    for row in table_rows(table)? {
+        let cells = row_cells(&row);
+        let date_time = match row_date_time(cells.first(), &mut current_date)? {
+            Some(date_time) => date_time,
+            None => continue,
+        };
+
+        // start time, state, duration, drained percentage, drained energy
+        records.push(BatteryUsageRecord {
+            date_time,
+            state: parse_power_state(&nth_text(&cells, 1)),
+            duration: parse_duration(&nth_text(&cells, 2)),
+            energy_drained_percentage: parse_percentage(&nth_text(&cells, 3)),
+            energy_drained: parse_number(&nth_text(&cells, 4)).map(Capacity::milliwatt_hours),
+        });
+    }
+
+    Ok(records)
+}
+
+fn parse_usage_history(table: &ElementRef) -> Result<Vec<UsageHistoryRecord>, BatteryError> {
+    let mut records = Vec::new();
+
+    for row in table_rows(table)? {
+        let cells = row_cells(&row);
+        let period = match parse_period(&nth_text(&cells, 0)) {
+            Some(period) => period,
+            None => continue,
+        };
+
+        // period, battery active and connected standby, ac active and connected standby
+        records.push(UsageHistoryRecord {
+            period,
+            battery_active: parse_duration(&nth_text(&cells, 1)),
+            battery_connected_standby: parse_duration(&nth_text(&cells, 2)),
+            ac_active: parse_duration(&nth_text(&cells, 3)),
+            ac_connected_standby: parse_duration(&nth_text(&cells, 4)),
+        });
+    }
+
+    Ok(records)
+}
+
+fn parse_capacity_history(table: &ElementRef) -> Result<Vec<CapacityHistoryRecord>, BatteryError> {
+    let mut records = Vec::new();
+
+    for row in table_rows(table)? {
+        let cells = row_cells(&row);
+        let period = match parse_period(&nth_text(&cells, 0)) {
+            Some(period) => period,
+            None => continue,
+        };
+
+        // period, full charge capacity, design capacity
+        let full_charge_capacity = parse_number(&nth_text(&cells, 1));
+        let design_capacity = parse_number(&nth_text(&cells, 2));
+
+        if let (Some(full_charge_capacity), Some(design_capacity)) =
+            (full_charge_capacity, design_capacity)
+        {
+            records.push(CapacityHistoryRecord {
+                period,
+                full_charge_capacity: Capacity::milliwatt_hours(full_charge_capacity),
+                design_capacity: Capacity::milliwatt_hours(design_capacity),
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+fn parse_life_estimates(table: &ElementRef) -> Result<Vec<LifeEstimateRecord>, BatteryError> {
+    let mut records = Vec::new();
+
+    for row in table_rows(table)? {
+        let cells = row_cells(&row);
+
+        // period, active and connected standby at full charge, active and connected standby at design capacity
+        let durations: Vec<Option<Duration>> = (1..=4)
+            .map(|n| parse_duration(&nth_text(&cells, n)))
+            .collect();
+
+        // the "Since OS install" row doesn't have a period
+        let period = parse_period(&nth_text(&cells, 0));
+        if period.is_none() && durations.iter().all(Option::is_none) {
+            continue;
+        }
+
+        records.push(LifeEstimateRecord {
+            period,
+            full_charge_active: durations[0],
+            full_charge_connected_standby: durations[1],
+            design_capacity_active: durations[2],
+            design_capacity_connected_standby: durations[3],
+        });
+    }
+
+    Ok(records)
+}
+
+fn selector(selectors: &str) -> Result<Selector, BatteryError> {
+    Selector::parse(selectors).map_err(|err| BatteryError::Parse {
+        line: None,
+        message: format!("Invalid selector {selectors}: {err:?}"),
+    })
+}
+
+/// The first table following the given heading
+fn next_table<'a>(heading: &ElementRef<'a>) -> Option<ElementRef<'a>> {
+    heading
+        .next_siblings()
+        .filter_map(ElementRef::wrap)
+        .take_while(|element| element.value().name() != "h2")
+        .find(|element| element.value().name() == "table")
+}
+
+/// All the rows of the table except the ones in the header
+fn table_rows<'a>(table: &ElementRef<'a>) -> Result<Vec<ElementRef<'a>>, BatteryError> {
+    let row_selector = selector("tr")?;
+
+    Ok(table
+        .select(&row_selector)
+        .filter(|row| {
+            !row.ancestors()
+                .filter_map(ElementRef::wrap)
+                .any(|ancestor| ancestor.value().name() == "thead")
+        })
+        .collect())
+}
+
+fn cell_text(element: &ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// The cells of the row in their order, except the empty ones separating the groups of columns.
+///
+/// The cells are read by their position rather than by their class, since the report replaces the class
+/// of a missing value with "nullValue".
+fn row_cells<'a>(row: &ElementRef<'a>) -> Vec<ElementRef<'a>> {
+    row.children()
+        .filter_map(ElementRef::wrap)
+        .filter(|cell| cell.value().name() == "td")
+        .filter(|cell| !cell.value().classes().any(|class| class == "colBreak"))
+        .collect()
+}
+
+fn nth_text(cells: &[ElementRef], n: usize) -> String {
+    cells.get(n).map(cell_text).unwrap_or_default()
+}
+
+/// The date is only shown on the first row of each day, so the last seen date is carried over.
+fn row_date_time(
+    cell: Option<&ElementRef>,
+    current_date: &mut Option<NaiveDate>,
+) -> Result<Option<DateTime<Utc>>, BatteryError> {
+    let cell = match cell {
+        Some(cell) => cell,
+        None => return Ok(None),
+    };
+    let date_text = cell_text_in(cell, "span.date")?;
+    let time_text = cell_text_in(cell, "span.time")?;
+
+    if let Ok(date) = NaiveDate::parse_from_str(&date_text, "%Y-%m-%d") {
+        *current_date = Some(date);
+    }
+
+    let (date, time) = match (
+        current_date,
+        NaiveTime::parse_from_str(&time_text, "%H:%M:%S"),
+    ) {
+        (Some(date), Ok(time)) => (*date, time),
+        _ => return Ok(None),
+    };
+
+    Ok(local_to_utc(NaiveDateTime::new(date, time)))
+}
+
+fn cell_text_in(element: &ElementRef, selectors: &str) -> Result<String, BatteryError> {
+    let cell_selector = selector(selectors)?;
+    Ok(element
+        .select(&cell_selector)
+        .next()
+        .map(|cell| cell_text(&cell))
+        .unwrap_or_default())
+}
+
+pub(crate) fn local_to_utc(date_time: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&date_time)
+        .earliest()
+        .map(|local| local.with_timezone(&Utc))
+}
+
+fn parse_power_state(text: &str) -> PowerState {
+    match text.to_lowercase().as_str() {
+        "active" => PowerState::Active,
+        "connected standby" => PowerState::ConnectedStandby,
+        "suspended" => PowerState::Suspended,
+        "report generated" => PowerState::ReportGenerated,
+        _ => PowerState::Unknown,
+    }
+}
+
+/// Parses values like "12 %", "38,730 mWh", "-1,234 mWh" or "12.5", "-" and empty values are [None]
+fn parse_number(text: &str) -> Option<f64> {
+    // only the unit and the thousands separators are removed, so that the sign and the decimals are kept
+    text.trim()
+        .trim_end_matches(|c: char| c.is_alphabetic() || c == '%' || c.is_whitespace())
+        .replace(',', "")
+        .parse()
+        .ok()
+}
+
+fn parse_percentage(text: &str) -> Option<i32> {
+    parse_number(text).map(|percentage| percentage.round() as i32)
+}
+
+/// Parses "YYYY-MM-DD - YYYY-MM-DD" or a single "YYYY-MM-DD"
+fn parse_period(text: &str) -> Option<ReportPeriod> {
+    let mut dates = text
+        .split(" - ")
+        .map(|date| NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d"));
+
+    let start = dates.next()?.ok()?;
+    let end = match dates.next() {
+        Some(end) => end.ok()?,
+        None => start,
+    };
+
+    Some(ReportPeriod { start, end })
+}
+
+/// Parses durations like "1:02:03" (h:mm:ss) or "2:01:02:03" (d:hh:mm:ss), "-" is [None]
+fn parse_duration(text: &str) -> Option<Duration> {
+    let parts = text
+        .split(':')
+        .map(|part| part.trim().parse::<i64>())
+        .collect::<Result<Vec<i64>, _>>()
+        .ok()?;
+
+    let seconds = match parts.as_slice() {
+        [h, m, s] => h * 3600 + m * 60 + s,
+        [d, h, m, s] => d * 86400 + h * 3600 + m * 60 + s,
+        _ => return None,
+    };
+
+    Some(Duration::seconds(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"<html><body>
+<h2>Recent usage</h2>
+<table>
+<thead><tr><td>START TIME</td><td>STATE</td><td>SOURCE</td><td colspan="2">CAPACITY REMAINING</td></tr></thead>
+<tr class="even 1"><td class="dateTime"><span class="date">2023-10-17 </span><span class="time">10:31:48</span></td><td class="state">Active</td><td class="acdc">AC</td><td class="percent">60 %</td><td class="mw">34,760 mWh</td></tr>
+<tr class="odd 2"><td class="dateTime"><span class="date"></span><span class="time">11:02:10</span></td><td class="state">Connected standby</td><td class="acdc">Battery</td><td class="nullValue">-</td><td class="mw">33,120 mWh</td></tr>
+<tr class="even 3"><td class="dateTime"><span class="date"></span><span class="time">12:00:00</span></td><td class="state">Suspended</td><td class="acdc">Battery</td><td class="percent">55 %</td><td class="nullValue">-</td></tr>
+</table>
+<h2>Battery usage</h2>
+<table>
+<tr class="even 1"><td class="dateTime"><span class="date">2023-10-17 </span><span class="time">11:02:10</span></td><td class="state">Connected standby</td><td class="hms">0:57:50</td><td class="nullValue">-</td><td class="mw">-1,234 mWh</td></tr>
+</table>
+<h2>Usage history</h2>
+<table>
+<tr class="even 1"><td class="dateTime">2023-10-09 - 2023-10-15</td><td class="hms">4:12:00</td><td class="nullValue">-</td><td class="colBreak"></td><td class="hms">10:00:00</td><td class="hms">1:02:03:04</td></tr>
+</table>
+<h2>Battery capacity history</h2>
+<table>
+<tr class="even 1"><td class="dateTime">2023-10-16</td><td class="mw">45,000 mWh</td><td class="mw">50,000 mWh</td></tr>
+<tr class="odd 2"><td class="dateTime">2023-10-17</td><td class="nullValue">-</td><td class="mw">50,000 mWh</td></tr>
+</table>
+<h2>Battery life estimates</h2>
+<table>
+<tr class="even 1"><td class="dateTime">2023-10-16</td><td class="nullValue">-</td><td class="hms">100:00:00</td><td class="colBreak"></td><td class="hms">6:00:00</td><td class="hms">120:00:00</td></tr>
+<tr class="odd 2"><td class="dateTime">Since OS install</td><td class="hms">5:00:00</td><td class="nullValue">-</td><td class="colBreak"></td><td class="nullValue">-</td><td class="nullValue">-</td></tr>
+</table>
+</body></html>"#;
+
+    fn date_time(date: &str) -> DateTime<Utc> {
+        local_to_utc(NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()).unwrap()
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn null_values_do_not_shift_the_columns() {
+        let report = parse_html_report(REPORT).unwrap();
+
+        let recent = &report.recent_usage;
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent[0].date_time, date_time("2023-10-17 10:31:48"));
+        assert_eq!(recent[0].source, PowerSource::Ac);
+        assert_eq!(recent[0].percentage, Some(60));
+        assert_eq!(recent[0].capacity, Some(Capacity::milliwatt_hours(34760.0)));
+        // the date is carried over from the previous row
+        assert_eq!(recent[1].date_time, date_time("2023-10-17 11:02:10"));
+        assert_eq!(recent[1].state, PowerState::ConnectedStandby);
+        assert_eq!(recent[1].percentage, None);
+        assert_eq!(recent[1].capacity, Some(Capacity::milliwatt_hours(33120.0)));
+        assert_eq!(recent[2].percentage, Some(55));
+        assert_eq!(recent[2].capacity, None);
+
+        // only the rows with a capacity are in the history
+        assert_eq!(report.history.len(), 2);
+        assert_eq!(
+            report.history.last().unwrap().state,
+            ChargeState::Discharging
+        );
+
+        let usage = &report.battery_usage[0];
+        assert_eq!(usage.duration, Some(Duration::seconds(57 * 60 + 50)));
+        assert_eq!(usage.energy_drained_percentage, None);
+        assert_eq!(
+            usage.energy_drained,
+            Some(Capacity::milliwatt_hours(-1234.0))
+        );
+
+        let history = &report.usage_history[0];
+        assert_eq!(history.period.start, date("2023-10-09"));
+        assert_eq!(history.period.end, date("2023-10-15"));
+        assert_eq!(
+            history.battery_active,
+            Some(Duration::hours(4) + Duration::minutes(12))
+        );
+        assert_eq!(history.battery_connected_standby, None);
+        assert_eq!(history.ac_active, Some(Duration::hours(10)));
+        assert_eq!(
+            history.ac_connected_standby,
+            Some(Duration::days(1) + Duration::seconds(2 * 3600 + 3 * 60 + 4))
+        );
+
+        // rows missing either capacity are skipped
+        assert_eq!(report.capacity_history.len(), 1);
+        let capacity = &report.capacity_history[0];
+        assert_eq!(
+            capacity.full_charge_capacity,
+            Capacity::milliwatt_hours(45000.0)
+        );
+        assert_eq!(capacity.design_capacity, Capacity::milliwatt_hours(50000.0));
+
+        let estimates = &report.life_estimates;
+        assert_eq!(estimates.len(), 2);
+        assert_eq!(estimates[0].full_charge_active, None);
+        assert_eq!(
+            estimates[0].full_charge_connected_standby,
+            Some(Duration::hours(100))
+        );
+        assert_eq!(
+            estimates[0].design_capacity_active,
+            Some(Duration::hours(6))
+        );
+        assert_eq!(estimates[1].period, None);
+        assert_eq!(estimates[1].full_charge_active, Some(Duration::hours(5)));
+        assert_eq!(estimates[1].design_capacity_active, None);
+    }
+
+    #[test]
+    fn numbers_keep_their_sign_and_decimals() {
+        assert_eq!(parse_number("38,730 mWh"), Some(38730.0));
+        assert_eq!(parse_number("-1,234"), Some(-1234.0));
+        assert_eq!(parse_number("12.5"), Some(12.5));
+        assert_eq!(parse_number("12 %"), Some(12.0));
+        assert_eq!(parse_number("-"), None);
+        assert_eq!(parse_number(""), None);
+    }
+}
@@ -0,0 +1,354 @@
+use chrono::DateTime;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::read_xml::BatteryInfo;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+use crate::units::CapacityUnit;
+
+/// Single `AppleSmartBattery` entry of `ioreg -rn AppleSmartBattery`.
+///
+/// Capacities are in mAh and voltage in mV as reported by ioreg. On Apple silicon the
+/// `CurrentCapacity` and `MaxCapacity` are percentages, so the raw values are preferred when present.
+#[derive(Debug, Clone, Default)]
+pub struct IoregBattery {
+    pub update_time: Option<DateTime<Utc>>,
+    pub cycle_count: Option<u32>,
+    pub design_capacity: Option<i64>,
+    pub max_capacity: Option<i64>,
+    pub current_capacity: Option<i64>,
+    pub voltage: Option<i64>,
+    pub is_charging: Option<bool>,
+    pub external_connected: Option<bool>,
+    pub fully_charged: Option<bool>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub device_name: Option<String>,
+}
+
+impl IoregBattery {
+    pub fn state(&self) -> ChargeState {
+        match (
+            self.fully_charged,
+            self.is_charging,
+            self.external_connected,
+        ) {
+            (Some(true), _, _) => ChargeState::FullyCharged,
+            (_, Some(true), _) => ChargeState::Charging,
+            (_, _, Some(true)) => ChargeState::PendingCharge,
+            (_, _, Some(false)) => ChargeState::Discharging,
+            _ => ChargeState::Unknown,
+        }
+    }
+
+    /// Remaining capacity in mWh, converted from mAh at the current voltage
+    pub fn capacity(&self) -> Option<Capacity> {
+        Capacity::milliamp_hours(self.current_capacity? as f64)
+            .to_with_voltage(CapacityUnit::MilliwattHour, self.voltage? as f64)
+    }
+
+    /// Battery metadata with the capacities in mAh
+    pub fn battery_info(&self) -> BatteryInfo {
+        BatteryInfo {
+            id: self.device_name.clone(),
+            manufacturer: self.manufacturer.clone(),
+            serial_number: self.serial_number.clone(),
+            chemistry: None,
+            design_capacity: self
+                .design_capacity
+                .map(|capacity| Capacity::milliamp_hours(capacity as f64)),
+            full_charge_capacity: self
+                .max_capacity
+                .map(|capacity| Capacity::milliamp_hours(capacity as f64)),
+            cycle_count: self.cycle_count,
+        }
+    }
+}
+
+/// Reads the saved output of `pmset -g log` from given path.
+///
+/// Only the lines containing "Using Batt (Charge:NN%)" or "Using AC (Charge:NN%)" are used,
+/// the capacity of the records is the percentage.
+///
+/// # Returns
+/// The [BatteryTimeSeries] of [BatteryHistoryRecord]s ordered by their date_time,
+/// or the error when a "Using" line doesn't have a readable charge.
+pub fn get_pmset_data(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    parse_pmset_log(&fs::read_to_string(path)?)
+}
+
+pub fn parse_pmset_log(contents: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut series = BatteryTimeSeries::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        // e.g. "2023-10-20 09:13:45 +0200 Sleep  Entering Sleep state ... Using Batt (Charge:87%) 1234 secs"
+        let date_time = match line
+            .get(..25)
+            .and_then(|prefix| DateTime::parse_from_str(prefix, "%Y-%m-%d %H:%M:%S %z").ok())
+        {
+            Some(date_time) => date_time.with_timezone(&Utc),
+            None => continue,
+        };
+
+        let using = match line.find("Using ") {
+            Some(index) => &line[index + "Using ".len()..],
+            None => continue,
+        };
+
+        let on_ac = if using.starts_with("AC") {
+            true
+        } else if using.starts_with("Batt") {
+            false
+        } else {
+            continue;
+        };
+
+        let percentage = match using
+            .split_once("Charge:")
+            .and_then(|(_, rest)| rest.split_once('%'))
+            .and_then(|(percentage, _)| percentage.trim().parse::<i32>().ok())
+        {
+            Some(percentage) => percentage,
+            None => {
+                return Err(BatteryError::Parse {
+                    line: Some(i as u64 + 1),
+                    message: format!("Expected the charge as \"(Charge:NN%)\", found {line}"),
+                })
+            }
+        };
+
+        series.insert(BatteryHistoryRecord {
+            date_time,
+            capacity: Capacity::percent(percentage as f64),
+            state: match (on_ac, percentage) {
+                (true, 100) => ChargeState::FullyCharged,
+                (true, _) => ChargeState::Charging,
+                (false, _) => ChargeState::Discharging,
+            },
+        });
+    }
+
+    Ok(series)
+}
+
+/// Reads one or more saved outputs of `ioreg -rn AppleSmartBattery` from given path.
+///
+/// # Returns
+/// The batteries in the same order as the file, or the error when a numeric property isn't a number.
+pub fn get_ioreg_batteries(path: &str) -> Result<Vec<IoregBattery>, BatteryError> {
+    parse_ioreg(&fs::read_to_string(path)?)
+}
+
+/// Reads the snapshots of `ioreg -rn AppleSmartBattery` into the same shape as [crate::read_data::get_data],
+/// snapshots without `UpdateTime`, `Voltage` or a current capacity are skipped.
+pub fn get_ioreg_data(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut series = BatteryTimeSeries::new();
+
+    for battery in get_ioreg_batteries(path)? {
+        if let (Some(date_time), Some(capacity)) = (battery.update_time, battery.capacity()) {
+            series.insert(BatteryHistoryRecord {
+                date_time,
+                capacity,
+                state: battery.state(),
+            });
+        }
+    }
+
+    Ok(series)
+}
+
+pub fn parse_ioreg(contents: &str) -> Result<Vec<IoregBattery>, BatteryError> {
+    let mut batteries = Vec::new();
+    // the values along with their line, starting at 1
+    let mut properties: Option<HashMap<String, (u64, String)>> = None;
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim().trim_start_matches('|').trim();
+
+        // each snapshot starts with "+-o AppleSmartBattery  <class AppleSmartBattery, ...>"
+        if line.starts_with("+-o") {
+            if let Some(properties) = properties.take() {
+                batteries.push(ioreg_battery(&properties)?);
+            }
+            if line.contains("AppleSmartBattery") {
+                properties = Some(HashMap::new());
+            }
+            continue;
+        }
+
+        // only the top level properties e.g. "CycleCount" = 123
+        if let (Some(properties), Some(line)) = (properties.as_mut(), line.strip_prefix('"')) {
+            if let Some((key, value)) = line.split_once("\" = ") {
+                properties.insert(key.to_string(), (i as u64 + 1, value.trim().to_string()));
+            }
+        }
+    }
+
+    if let Some(properties) = properties {
+        batteries.push(ioreg_battery(&properties)?);
+    }
+
+    Ok(batteries)
+}
+
+fn ioreg_battery(
+    properties: &HashMap<String, (u64, String)>,
+) -> Result<IoregBattery, BatteryError> {
+    let number = |key: &str| match properties.get(key) {
+        Some((line, v)) => v.parse::<i64>().map(Some).map_err(|_| BatteryError::Parse {
+            line: Some(*line),
+            message: format!("Expected a number for {key}, found {v}"),
+        }),
+        None => Ok(None),
+    };
+    let boolean = |key: &str| match properties.get(key).map(|(_, v)| v.as_str()) {
+        Some("Yes") => Some(true),
+        Some("No") => Some(false),
+        _ => None,
+    };
+    let string = |key: &str| {
+        properties
+            .get(key)
+            .map(|(_, v)| v.trim_matches('"').to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    Ok(IoregBattery {
+        update_time: number("UpdateTime")?.and_then(|v| DateTime::<Utc>::from_timestamp(v, 0)),
+        cycle_count: number("CycleCount")?.map(|v| v as u32),
+        design_capacity: number("DesignCapacity")?,
+        // the fallbacks aren't read when the raw values are present
+        max_capacity: match number("AppleRawMaxCapacity")? {
+            Some(v) => Some(v),
+            None => number("MaxCapacity")?,
+        },
+        current_capacity: match number("AppleRawCurrentCapacity")? {
+            Some(v) => Some(v),
+            None => number("CurrentCapacity")?,
+        },
+        voltage: number("Voltage")?,
+        is_charging: boolean("IsCharging"),
+        external_connected: boolean("ExternalConnected"),
+        fully_charged: boolean("FullyCharged"),
+        serial_number: string("Serial").or(string("BatterySerialNumber")),
+        manufacturer: string("Manufacturer"),
+        device_name: string("DeviceName"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PMSET_LOG: &str = "\
+Time stamp                Domain                  Message                                                                          Duration  Delay
+==========                ======                  =======                                                                          ========  =====
+2023-10-20 09:13:45 +0200 Sleep                 \tEntering Sleep state due to 'Idle Sleep':TCPKeepAlive=active Using Batt (Charge:87%) 1234 secs
+2023-10-20 09:34:19 +0200 Wake                  \tDarkWake to FullWake from Deep Idle [CDNVA] : due to UserActivity Assertion Using AC (Charge:85%)
+2023-10-20 10:00:00 +0200 Assertions            \tSummary- [System: PrevIdle DeclUser kDisp]
+2023-10-20 11:02:03 +0200 Wake                  \tWake from Deep Idle [CDNVA] : due to NUB.SPMI0.SW3 Using AC (Charge:100%)
+";
+
+    const IOREG: &str = r#"+-o AppleSmartBattery  <class AppleSmartBattery, id 0x100000254, registered, matched, active, busy 0 (0 ms), retain 8>
+    {
+      "FullyCharged" = No
+      "ExternalConnected" = Yes
+      "IsCharging" = Yes
+      "Voltage" = 12000
+      "CurrentCapacity" = 80
+      "AppleRawCurrentCapacity" = 4000
+      "AppleRawMaxCapacity" = 5000
+      "MaxCapacity" = 100
+      "DesignCapacity" = 6000
+      "CycleCount" = 123
+      "UpdateTime" = 1697786025
+      "Serial" = "F5D1234"
+      "DeviceName" = "bq40z651"
+      "BatteryData" = {"Voltage"=11900,"DesignCapacity"=6000}
+    }
+"#;
+
+    fn date_time(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn pmset_percentages() {
+        let series = parse_pmset_log(PMSET_LOG).unwrap();
+        let records = series.iter().collect::<Vec<_>>();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].date_time, date_time("2023-10-20T07:13:45Z"));
+        assert_eq!(records[0].capacity, Capacity::percent(87.0));
+        assert_eq!(records[0].state, ChargeState::Discharging);
+        assert_eq!(records[1].capacity, Capacity::percent(85.0));
+        assert_eq!(records[1].state, ChargeState::Charging);
+        assert_eq!(records[2].capacity, Capacity::percent(100.0));
+        assert_eq!(records[2].state, ChargeState::FullyCharged);
+    }
+
+    #[test]
+    fn pmset_malformed_charge() {
+        let log = "2023-10-20 09:13:45 +0200 Sleep  Entering Sleep state Using Batt (Charge:high) 1234 secs\n\
+                   2023-10-20 09:34:19 +0200 Wake  Using AC (Charge:85%)";
+
+        assert!(matches!(
+            parse_pmset_log(log),
+            Err(BatteryError::Parse { line: Some(1), .. })
+        ));
+    }
+
+    #[test]
+    fn ioreg_capacity_at_the_voltage() {
+        let batteries = parse_ioreg(IOREG).unwrap();
+        assert_eq!(batteries.len(), 1);
+
+        let battery = &batteries[0];
+        assert_eq!(battery.update_time, Some(date_time("2023-10-20T07:13:45Z")));
+        assert_eq!(battery.cycle_count, Some(123));
+        // the raw values are preferred over the percentages
+        assert_eq!(battery.current_capacity, Some(4000));
+        assert_eq!(battery.max_capacity, Some(5000));
+        assert_eq!(battery.voltage, Some(12000));
+        assert_eq!(battery.state(), ChargeState::Charging);
+        assert_eq!(battery.serial_number.as_deref(), Some("F5D1234"));
+
+        // 4000 mAh at 12 V
+        assert_eq!(battery.capacity(), Some(Capacity::milliwatt_hours(48000.0)));
+        assert_eq!(
+            battery.battery_info().design_capacity,
+            Some(Capacity::milliamp_hours(6000.0))
+        );
+    }
+
+    #[test]
+    fn ioreg_malformed_number() {
+        let ioreg = IOREG.replace("\"Voltage\" = 12000", "\"Voltage\" = twelve");
+
+        assert!(matches!(
+            parse_ioreg(&ioreg),
+            Err(BatteryError::Parse { line: Some(6), .. })
+        ));
+
+        // the percentages aren't needed along with the raw values
+        let ioreg = IOREG
+            .replace("\"MaxCapacity\" = 100", "\"MaxCapacity\" = full")
+            .replace("\"CurrentCapacity\" = 80", "\"CurrentCapacity\" = most");
+        let batteries = parse_ioreg(&ioreg).unwrap();
+        assert_eq!(batteries[0].max_capacity, Some(5000));
+        assert_eq!(batteries[0].current_capacity, Some(4000));
+
+        let ioreg = ioreg.replace("\"AppleRawMaxCapacity\" = 5000", "");
+        assert!(matches!(
+            parse_ioreg(&ioreg),
+            Err(BatteryError::Parse { line: Some(10), .. })
+        ));
+    }
+}
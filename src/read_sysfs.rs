@@ -0,0 +1,428 @@
+use chrono::DateTime;
+use chrono::Utc;
+use csv::WriterBuilder;
+use serde::Serialize;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::error::BatteryError;
+use crate::health::HealthRecord;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::units::Capacity;
+use crate::units::CapacityUnit;
+
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys/class/power_supply";
+
+/// Single reading of a `BAT*` directory, the values are in the units used by sysfs
+/// i.e. µWh, µAh, µV and µW. Values which are not exposed by the driver are [None].
+#[derive(Debug, Clone)]
+pub struct SysfsSample {
+    pub battery: String,
+    pub date_time: DateTime<Utc>,
+    pub energy_now: Option<i64>,
+    pub energy_full: Option<i64>,
+    pub energy_full_design: Option<i64>,
+    pub charge_now: Option<i64>,
+    pub voltage_now: Option<i64>,
+    pub power_now: Option<i64>,
+    pub state: ChargeState,
+}
+
+impl SysfsSample {
+    /// Remaining capacity in mWh, calculated from `charge_now` and `voltage_now`
+    /// when the driver doesn't expose `energy_now`.
+    pub fn capacity(&self) -> Option<Capacity> {
+        match (self.energy_now, self.charge_now, self.voltage_now) {
+            (Some(energy_now), _, _) => Some(Capacity::milliwatt_hours(energy_now as f64 / 1000.0)),
+            (None, Some(charge_now), Some(voltage_now)) => {
+                Capacity::milliamp_hours(charge_now as f64 / 1000.0)
+                    .to_with_voltage(CapacityUnit::MilliwattHour, voltage_now as f64 / 1000.0)
+            }
+            _ => None,
+        }
+    }
+
+    /// `energy_full` and `energy_full_design` in mWh, [None] when the driver doesn't expose them.
+    pub fn health(&self) -> Option<HealthRecord> {
+        Some(HealthRecord {
+            date_time: self.date_time,
+            full_charge_capacity: Capacity::milliwatt_hours(self.energy_full? as f64 / 1000.0),
+            design_capacity: Capacity::milliwatt_hours(self.energy_full_design? as f64 / 1000.0),
+        })
+    }
+}
+
+/// Samples the batteries exposed under the power_supply class of sysfs.
+///
+/// The root is configurable so that a fake directory tree can be used instead of `/sys`.
+#[derive(Debug, Clone)]
+pub struct SysfsSampler {
+    root: PathBuf,
+    interval: std::time::Duration,
+}
+
+impl Default for SysfsSampler {
+    fn default() -> Self {
+        SysfsSampler::new(DEFAULT_SYSFS_ROOT)
+    }
+}
+
+impl SysfsSampler {
+    /// Sampler reading from given root (usually [DEFAULT_SYSFS_ROOT]) every minute.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        SysfsSampler {
+            root: root.as_ref().to_path_buf(),
+            interval: std::time::Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: std::time::Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn interval(&self) -> std::time::Duration {
+        self.interval
+    }
+
+    /// Sorted paths of all the `BAT*` directories under the root
+    pub fn batteries(&self) -> Result<Vec<PathBuf>, BatteryError> {
+        let mut batteries = Vec::new();
+
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with("BAT") {
+                batteries.push(entry.path());
+            }
+        }
+
+        batteries.sort();
+        Ok(batteries)
+    }
+
+    /// Reads all the batteries once
+    ///
+    /// # Parameters
+    /// date_time: time of the reading, usually [Utc::now]
+    pub fn sample(&self, date_time: DateTime<Utc>) -> Result<Vec<SysfsSample>, BatteryError> {
+        let batteries = self.batteries()?;
+        if batteries.is_empty() {
+            return Err(BatteryError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No batteries found in {}", self.root.display()),
+            )));
+        }
+
+        Ok(batteries
+            .iter()
+            .map(|battery| SysfsSample {
+                battery: battery
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                date_time,
+                energy_now: read_value(battery, "energy_now"),
+                energy_full: read_value(battery, "energy_full"),
+                energy_full_design: read_value(battery, "energy_full_design"),
+                charge_now: read_value(battery, "charge_now"),
+                voltage_now: read_value(battery, "voltage_now"),
+                power_now: read_value(battery, "power_now"),
+                state: match fs::read_to_string(battery.join("status")) {
+                    Ok(status) => parse_status(&status),
+                    Err(_) => ChargeState::Unknown,
+                },
+            })
+            .collect())
+    }
+
+    /// Reads all the batteries once and combines them into a single record,
+    /// the capacities are summed up and the battery being charged or discharged wins over the other states.
+    pub fn sample_record(
+        &self,
+        date_time: DateTime<Utc>,
+    ) -> Result<BatteryHistoryRecord, BatteryError> {
+        let samples = self.sample(date_time)?;
+
+        let mut capacity = Capacity::milliwatt_hours(0.0);
+        for sample in samples.iter() {
+            let sample_capacity = sample.capacity().ok_or_else(|| {
+                BatteryError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "Neither energy_now nor charge_now and voltage_now are available for {}",
+                        sample.battery
+                    ),
+                ))
+            })?;
+            capacity = add_capacity(capacity, sample_capacity)?;
+        }
+
+        let state = samples
+            .iter()
+            .map(|sample| sample.state)
+            .reduce(|combined, state| match (combined, state) {
+                (ChargeState::Charging, _) | (_, ChargeState::Charging) => ChargeState::Charging,
+                (ChargeState::Discharging, _) | (_, ChargeState::Discharging) => {
+                    ChargeState::Discharging
+                }
+                (combined, state) if combined == state => combined,
+                _ => ChargeState::Unknown,
+            })
+            .unwrap_or(ChargeState::Unknown);
+
+        Ok(BatteryHistoryRecord {
+            date_time: samples[0].date_time,
+            capacity,
+            state,
+        })
+    }
+
+    /// Reads the full charge and design capacities of all the batteries once and sums them up.
+    pub fn sample_health(&self, date_time: DateTime<Utc>) -> Result<HealthRecord, BatteryError> {
+        let samples = self.sample(date_time)?;
+
+        let mut full_charge_capacity = Capacity::milliwatt_hours(0.0);
+        let mut design_capacity = Capacity::milliwatt_hours(0.0);
+        for sample in samples.iter() {
+            let health = sample.health().ok_or_else(|| {
+                BatteryError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "energy_full and energy_full_design aren't available for {}",
+                        sample.battery
+                    ),
+                ))
+            })?;
+            full_charge_capacity = add_capacity(full_charge_capacity, health.full_charge_capacity)?;
+            design_capacity = add_capacity(design_capacity, health.design_capacity)?;
+        }
+
+        Ok(HealthRecord {
+            date_time: samples[0].date_time,
+            full_charge_capacity,
+            design_capacity,
+        })
+    }
+
+    /// Appends a row to the csv in given path, in the same format read by [crate::health::get_health_data].
+    /// The header is written only when the file is new or empty.
+    pub fn append_health<P: AsRef<Path>>(
+        &self,
+        path: P,
+        date_time: DateTime<Utc>,
+    ) -> Result<HealthRecord, BatteryError> {
+        let record = self.sample_health(date_time)?;
+        append_to_csv(path, &record)?;
+        Ok(record)
+    }
+
+    /// Appends a row to the csv in given path, in the same format read by [crate::read_data::get_data].
+    /// The header is written only when the file is new or empty.
+    pub fn append_record<P: AsRef<Path>>(
+        &self,
+        path: P,
+        date_time: DateTime<Utc>,
+    ) -> Result<BatteryHistoryRecord, BatteryError> {
+        let record = self.sample_record(date_time)?;
+        append_to_csv(path, &record)?;
+        Ok(record)
+    }
+
+    /// Appends a row every interval at the current time, forever when `number_of_samples` is [None].
+    pub fn log_to_csv<P: AsRef<Path>>(
+        &self,
+        path: P,
+        number_of_samples: Option<usize>,
+    ) -> Result<(), BatteryError> {
+        let mut taken = 0;
+
+        loop {
+            self.append_record(path.as_ref(), Utc::now())?;
+            taken += 1;
+
+            if number_of_samples.is_some_and(|number_of_samples| taken >= number_of_samples) {
+                return Ok(());
+            }
+
+            std::thread::sleep(self.interval);
+        }
+    }
+}
+
+fn read_value(battery: &Path, name: &str) -> Option<i64> {
+    fs::read_to_string(battery.join(name))
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// The `status` file, e.g. "Charging" or "Not charging"
+fn parse_status(status: &str) -> ChargeState {
+    match status.trim() {
+        "Charging" => ChargeState::Charging,
+        "Discharging" => ChargeState::Discharging,
+        "Full" => ChargeState::FullyCharged,
+        "Not charging" => ChargeState::PendingCharge,
+        _ => ChargeState::Unknown,
+    }
+}
+
+/// Sum in the unit of `sum`
+fn add_capacity(sum: Capacity, capacity: Capacity) -> Result<Capacity, BatteryError> {
+    sum.checked_add(capacity)
+        .ok_or(BatteryError::IncompatibleUnits {
+            from: capacity.unit(),
+            to: sum.unit(),
+        })
+}
+
+fn append_to_csv<P: AsRef<Path>, T: Serialize>(path: P, record: &T) -> Result<(), BatteryError> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+
+    let mut wtr = WriterBuilder::new().has_headers(is_empty).from_writer(file);
+    wtr.serialize(record)?;
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake power_supply tree, removed when dropped
+    struct FakeSysfs(PathBuf);
+
+    impl FakeSysfs {
+        fn new(name: &str, batteries: &[(&str, &[(&str, &str)])]) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "battery-data-analysis-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&root);
+
+            for (battery, files) in batteries {
+                let directory = root.join(battery);
+                fs::create_dir_all(&directory).unwrap();
+                for (file, contents) in files.iter() {
+                    fs::write(directory.join(file), format!("{contents}\n")).unwrap();
+                }
+            }
+            fs::create_dir_all(root.join("AC")).unwrap();
+
+            FakeSysfs(root)
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn date_time() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2023-10-20T08:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn reads_energy_and_charge_batteries() {
+        let sysfs = FakeSysfs::new(
+            "read",
+            &[
+                (
+                    "BAT0",
+                    &[
+                        ("energy_now", "40000000"),
+                        ("energy_full", "50000000"),
+                        ("energy_full_design", "60000000"),
+                        ("status", "Full"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[
+                        ("charge_now", "2000000"),
+                        ("voltage_now", "12000000"),
+                        ("status", "Not charging"),
+                    ],
+                ),
+            ],
+        );
+        let sampler = SysfsSampler::new(&sysfs.0);
+
+        let samples = sampler.sample(date_time()).unwrap();
+        assert_eq!(samples.len(), 2);
+
+        assert_eq!(samples[0].battery, "BAT0");
+        assert_eq!(samples[0].date_time, date_time());
+        assert_eq!(samples[0].state, ChargeState::FullyCharged);
+        assert_eq!(
+            samples[0].capacity(),
+            Some(Capacity::milliwatt_hours(40000.0))
+        );
+        let health = samples[0].health().unwrap();
+        assert_eq!(
+            health.full_charge_capacity,
+            Capacity::milliwatt_hours(50000.0)
+        );
+        assert_eq!(health.design_capacity, Capacity::milliwatt_hours(60000.0));
+
+        // 2000 mAh at 12 V
+        assert_eq!(samples[1].battery, "BAT1");
+        assert_eq!(samples[1].state, ChargeState::PendingCharge);
+        assert_eq!(
+            samples[1].capacity(),
+            Some(Capacity::milliwatt_hours(24000.0))
+        );
+        assert!(samples[1].health().is_none());
+
+        let record = sampler.sample_record(date_time()).unwrap();
+        assert_eq!(record.date_time, date_time());
+        assert_eq!(record.capacity, Capacity::milliwatt_hours(64000.0));
+        assert_eq!(record.state, ChargeState::Unknown);
+    }
+
+    #[test]
+    fn charging_battery_wins() {
+        let sysfs = FakeSysfs::new(
+            "state",
+            &[
+                ("BAT0", &[("energy_now", "1000"), ("status", "Discharging")]),
+                ("BAT1", &[("energy_now", "1000"), ("status", "Charging")]),
+            ],
+        );
+
+        let record = SysfsSampler::new(&sysfs.0)
+            .sample_record(date_time())
+            .unwrap();
+        assert_eq!(record.state, ChargeState::Charging);
+        assert_eq!(record.capacity, Capacity::milliwatt_hours(2.0));
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        let sysfs = FakeSysfs::new("missing", &[("BAT0", &[("voltage_now", "12000000")])]);
+        let sampler = SysfsSampler::new(&sysfs.0);
+
+        assert!(sampler.sample_record(date_time()).is_err());
+        assert!(sampler.sample_health(date_time()).is_err());
+    }
+
+    #[test]
+    fn incompatible_capacities_are_errors() {
+        assert!(matches!(
+            add_capacity(Capacity::milliwatt_hours(0.0), Capacity::percent(50.0)),
+            Err(BatteryError::IncompatibleUnits { .. })
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+use chrono::DateTime;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::read_xml::BatteryInfo;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+pub const DEFAULT_UPOWER_DIR: &str = "/var/lib/upower";
+
+/// Row of an UPower history file.
+///
+/// The value is the percentage for `history-charge-*.dat`, the rate in W for `history-rate-*.dat`
+/// and the seconds for `history-time-full-*.dat` and `history-time-empty-*.dat`.
+#[derive(Debug, Clone)]
+pub struct UpowerHistoryEntry {
+    pub date_time: DateTime<Utc>,
+    pub value: f64,
+    pub state: ChargeState,
+}
+
+/// All the history files kept by UPower for a single device.
+#[derive(Debug, Clone, Default)]
+pub struct UpowerHistory {
+    pub charge: Vec<UpowerHistoryEntry>,
+    pub rate: Vec<UpowerHistoryEntry>,
+    pub time_full: Vec<UpowerHistoryEntry>,
+    pub time_empty: Vec<UpowerHistoryEntry>,
+}
+
+/// Reads a single UPower history file consisting of tab separated timestamp, value and state.
+///
+/// # Parameters
+/// path: [&str] of the history file e.g. `/var/lib/upower/history-charge-DELL_7FHD-54-123.dat`
+///
+/// # Returns
+/// The entries in the same order as the file.
+pub fn get_upower_history_file(path: &str) -> Result<Vec<UpowerHistoryEntry>, BatteryError> {
+    parse_upower_history(&fs::read_to_string(path)?)
+}
+
+pub fn parse_upower_history(contents: &str) -> Result<Vec<UpowerHistoryEntry>, BatteryError> {
+    let mut entries = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut columns = line.split('\t');
+        let (timestamp, value, state) = match (columns.next(), columns.next(), columns.next()) {
+            (Some(timestamp), Some(value), Some(state)) => (timestamp, value, state),
+            _ => {
+                return Err(BatteryError::Parse {
+                    line: Some(i as u64 + 1),
+                    message: format!("Expected 3 columns, found {line}"),
+                })
+            }
+        };
+        let invalid = |message: String| BatteryError::Parse {
+            line: Some(i as u64 + 1),
+            message,
+        };
+
+        let date_time = timestamp
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|timestamp| DateTime::<Utc>::from_timestamp(timestamp, 0))
+            .ok_or_else(|| invalid(format!("Invalid timestamp {timestamp}")))?;
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|_| invalid(format!("Invalid value {value}")))?;
+        let state = parse_upower_state(state.trim());
+
+        // upower writes zero with unknown state while the device is being probed
+        if value == 0.0 && matches!(state, ChargeState::Unknown) {
+            continue;
+        }
+
+        entries.push(UpowerHistoryEntry {
+            date_time,
+            value,
+            state,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Reads all the history files in given directory (usually [DEFAULT_UPOWER_DIR]).
+///
+/// # Returns
+/// The [UpowerHistory] of each device, keyed by the device id in the file name
+/// i.e. `DELL_7FHD-54-123` for `history-charge-DELL_7FHD-54-123.dat`.
+pub fn get_upower_history(dir: &str) -> Result<HashMap<String, UpowerHistory>, BatteryError> {
+    let mut histories: HashMap<String, UpowerHistory> = HashMap::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => continue,
+        };
+
+        let (kind, device) = match file_name
+            .strip_prefix("history-")
+            .and_then(|name| name.strip_suffix(".dat"))
+            .and_then(split_kind)
+        {
+            Some(kind_and_device) => kind_and_device,
+            None => continue,
+        };
+
+        let entries = get_upower_history_file(&path.to_string_lossy())?;
+        let history = histories.entry(device.to_string()).or_default();
+        match kind {
+            "charge" => history.charge = entries,
+            "rate" => history.rate = entries,
+            "time-full" => history.time_full = entries,
+            "time-empty" => history.time_empty = entries,
+            _ => {}
+        }
+    }
+
+    Ok(histories)
+}
+
+/// Reads a `history-charge-*.dat` file into the same shape as [crate::read_data::get_data].
+///
+/// # Parameters
+/// path: [&str] of the charge history file
+///
+/// full_capacity: full charge capacity used for converting the percentages,
+/// e.g. from [get_upower_info]. When [None], the capacity is the percentage itself.
+pub fn get_upower_data(
+    path: &str,
+    full_capacity: Option<Capacity>,
+) -> Result<BatteryTimeSeries, BatteryError> {
+    let mut series = BatteryTimeSeries::new();
+
+    for entry in get_upower_history_file(path)? {
+        let percentage = Capacity::percent(entry.value);
+        let capacity = match full_capacity {
+            Some(full_capacity) => percentage.of(full_capacity).unwrap_or(percentage),
+            None => percentage,
+        };
+
+        series.insert(BatteryHistoryRecord {
+            date_time: entry.date_time,
+            capacity,
+            state: entry.state,
+        });
+    }
+
+    Ok(series)
+}
+
+/// Reads the saved output of `upower -i <device>` or `upower --dump`.
+///
+/// # Returns
+/// The [BatteryInfo] of every device reporting an `energy-full`.
+pub fn get_upower_info(path: &str) -> Result<Vec<BatteryInfo>, BatteryError> {
+    Ok(parse_upower_info(&fs::read_to_string(path)?))
+}
+
+pub fn parse_upower_info(contents: &str) -> Vec<BatteryInfo> {
+    let mut batteries = Vec::new();
+
+    // each device is separated by an empty line in `upower --dump`
+    let mut current: HashMap<String, String> = HashMap::new();
+    for line in contents.lines().chain(std::iter::once("")) {
+        if line.trim().is_empty() {
+            if current.contains_key("energy-full") {
+                batteries.push(BatteryInfo {
+                    id: current.get("model").cloned(),
+                    manufacturer: current.get("vendor").cloned(),
+                    serial_number: current.get("serial").cloned(),
+                    chemistry: current.get("technology").cloned(),
+                    design_capacity: current.get("energy-full-design").and_then(|v| parse_wh(v)),
+                    full_charge_capacity: current.get("energy-full").and_then(|v| parse_wh(v)),
+                    cycle_count: current.get("charge-cycles").and_then(|v| v.parse().ok()),
+                });
+            }
+            current.clear();
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            current.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    batteries
+}
+
+fn parse_upower_state(state: &str) -> ChargeState {
+    match state {
+        "charging" => ChargeState::Charging,
+        "discharging" | "empty" => ChargeState::Discharging,
+        "fully-charged" => ChargeState::FullyCharged,
+        "pending-charge" => ChargeState::PendingCharge,
+        _ => ChargeState::Unknown,
+    }
+}
+
+/// Splits "charge-DEVICE" into ("charge", "DEVICE"), the kinds may contain hyphens themselves
+fn split_kind(name: &str) -> Option<(&str, &str)> {
+    ["time-full", "time-empty", "charge", "rate"]
+        .iter()
+        .find_map(|kind| {
+            name.strip_prefix(kind)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .map(|device| (*kind, device))
+        })
+}
+
+/// e.g. "49.17 Wh"
+fn parse_wh(value: &str) -> Option<Capacity> {
+    let number: f64 = value.split_whitespace().next()?.parse().ok()?;
+    Some(Capacity::watt_hours(number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{minute, TempDir};
+
+    const CHARGE_HISTORY: &str = "\
+1697788800\t0.000\tunknown
+1697788860\t45.000\tdischarging
+1697788920\t0.000\tdischarging
+
+1697788980\t100.000\tfully-charged
+1697789040\t80.000\tpending-charge
+1697789100\t50.000\tunknown
+";
+
+    const DUMP: &str = "\
+Device: /org/freedesktop/UPower/devices/line_power_AC
+  native-path:          AC
+  power supply:         yes
+  line-power
+    online:              yes
+
+Device: /org/freedesktop/UPower/devices/battery_BAT0
+  native-path:          BAT0
+  vendor:               SMP
+  model:                DELL 7FHD-54
+  serial:               123
+  power supply:         yes
+  battery
+    present:             yes
+    state:               discharging
+    energy:              40.1 Wh
+    energy-full:         49.17 Wh
+    energy-full-design:  56 Wh
+    charge-cycles:       123
+    percentage:          81%
+    technology:          lithium-polymer
+";
+
+    #[test]
+    fn charge_history() {
+        let entries = parse_upower_history(CHARGE_HISTORY).unwrap();
+
+        // the zero while probing is skipped, but not a known empty battery
+        let entries: Vec<(DateTime<Utc>, f64, ChargeState)> = entries
+            .iter()
+            .map(|entry| (entry.date_time, entry.value, entry.state))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (minute(1), 45.0, ChargeState::Discharging),
+                (minute(2), 0.0, ChargeState::Discharging),
+                (minute(3), 100.0, ChargeState::FullyCharged),
+                (minute(4), 80.0, ChargeState::PendingCharge),
+                (minute(5), 50.0, ChargeState::Unknown),
+            ]
+        );
+
+        assert!(matches!(
+            parse_upower_history("1697788800\t45.000\tcharging\n1697788860\tfull\tcharging\n"),
+            Err(BatteryError::Parse { line: Some(2), .. })
+        ));
+        assert!(matches!(
+            parse_upower_history("1697788800\t45.000\n"),
+            Err(BatteryError::Parse { line: Some(1), .. })
+        ));
+    }
+
+    #[test]
+    fn percentages_of_full_capacity() {
+        let dir = TempDir::new("upower-data");
+        let path = dir.write("history-charge-BAT0.dat", CHARGE_HISTORY);
+
+        let data = get_upower_data(&path, Some(Capacity::watt_hours(50.0))).unwrap();
+        assert_eq!(data.len(), 5);
+        assert_eq!(data.first().unwrap().capacity, Capacity::watt_hours(22.5));
+        assert_eq!(data.last().unwrap().capacity, Capacity::watt_hours(25.0));
+
+        let data = get_upower_data(&path, None).unwrap();
+        assert_eq!(data.first().unwrap().capacity, Capacity::percent(45.0));
+    }
+
+    #[test]
+    fn battery_info_of_dump() {
+        let dir = TempDir::new("upower-info");
+        let path = dir.write("upower.txt", DUMP);
+
+        // the line power has no energy
+        let batteries = get_upower_info(&path).unwrap();
+        assert_eq!(batteries.len(), 1);
+
+        let battery = &batteries[0];
+        assert_eq!(battery.id.as_deref(), Some("DELL 7FHD-54"));
+        assert_eq!(battery.manufacturer.as_deref(), Some("SMP"));
+        assert_eq!(battery.serial_number.as_deref(), Some("123"));
+        assert_eq!(battery.chemistry.as_deref(), Some("lithium-polymer"));
+        assert_eq!(battery.design_capacity, Some(Capacity::watt_hours(56.0)));
+        assert_eq!(
+            battery.full_charge_capacity,
+            Some(Capacity::watt_hours(49.17))
+        );
+        assert_eq!(battery.cycle_count, Some(123));
+    }
+}
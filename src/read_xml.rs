@@ -0,0 +1,294 @@
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use roxmltree::Document;
+use roxmltree::Node;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::read_html::CapacityHistoryRecord;
+use crate::read_html::ReportPeriod;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+/// Static information about an installed battery, values which are not reported are [None].
+#[derive(Debug, Clone, Default)]
+pub struct BatteryInfo {
+    pub id: Option<String>,
+    pub manufacturer: Option<String>,
+    pub serial_number: Option<String>,
+    pub chemistry: Option<String>,
+    pub design_capacity: Option<Capacity>,
+    pub full_charge_capacity: Option<Capacity>,
+    pub cycle_count: Option<u32>,
+}
+
+/// The contents of the xml battery report which are of interest to us.
+#[derive(Debug, Clone, Default)]
+pub struct XmlBatteryReport {
+    pub history: BatteryTimeSeries,
+    pub batteries: Vec<BatteryInfo>,
+    pub capacity_history: Vec<CapacityHistoryRecord>,
+}
+
+/// Reads the xml generated by `powercfg /batteryreport /xml` from given path.
+///
+/// # Parameters
+/// path: [&str] of the battery report xml
+///
+/// # Returns
+/// The [XmlBatteryReport] consisting of the remaining capacities of the recent usage entries,
+/// the installed batteries and the capacity history.
+pub fn get_xml_report(path: &str) -> Result<XmlBatteryReport, BatteryError> {
+    let contents = std::fs::read_to_string(path)?;
+    parse_xml_report(&contents)
+}
+
+/// Same as [get_xml_report] but only returns the history of remaining capacities.
+pub fn get_xml_data(path: &str) -> Result<BatteryTimeSeries, BatteryError> {
+    Ok(get_xml_report(path)?.history)
+}
+
+pub fn parse_xml_report(contents: &str) -> Result<XmlBatteryReport, BatteryError> {
+    let document = Document::parse(contents)?;
+    let root = document.root_element();
+
+    if root.tag_name().name() != "BatteryReport" {
+        return Err(BatteryError::Parse {
+            line: None,
+            message: format!(
+                "Expected BatteryReport as the root element, found {}",
+                root.tag_name().name()
+            ),
+        });
+    }
+
+    let mut report = XmlBatteryReport::default();
+
+    for battery in elements(&root, "Batteries")
+        .iter()
+        .flat_map(|batteries| elements(batteries, "Battery"))
+    {
+        report.batteries.push(BatteryInfo {
+            id: child_text(&battery, "Id"),
+            manufacturer: child_text(&battery, "Manufacturer"),
+            serial_number: child_text(&battery, "SerialNumber"),
+            chemistry: child_text(&battery, "Chemistry"),
+            design_capacity: child_text(&battery, "DesignCapacity").and_then(|v| parse_mwh(&v)),
+            full_charge_capacity: child_text(&battery, "FullChargeCapacity")
+                .and_then(|v| parse_mwh(&v)),
+            cycle_count: child_text(&battery, "CycleCount").and_then(|v| v.parse().ok()),
+        });
+    }
+
+    for entry in elements(&root, "RecentUsage")
+        .iter()
+        .flat_map(|usage| elements(usage, "UsageEntry"))
+    {
+        // the entries without remaining capacity aren't of any use
+        let capacity = match entry.attribute("ChargeCapacity").and_then(parse_mwh) {
+            Some(capacity) => capacity,
+            None => continue,
+        };
+
+        let date_time = match entry.attribute("Timestamp").and_then(parse_timestamp) {
+            Some(date_time) => date_time,
+            None => {
+                return Err(BatteryError::Parse {
+                    line: Some(document.text_pos_at(entry.range().start).row as u64),
+                    message: "Invalid Timestamp in UsageEntry".to_string(),
+                })
+            }
+        };
+
+        report.history.insert(BatteryHistoryRecord {
+            date_time,
+            capacity,
+            state: match entry.attribute("Ac") {
+                Some("1") => ChargeState::Charging,
+                Some("0") => ChargeState::Discharging,
+                _ => ChargeState::Unknown,
+            },
+        });
+    }
+
+    for entry in elements(&root, "History")
+        .iter()
+        .flat_map(|history| elements(history, "HistoryEntry"))
+    {
+        let start = entry.attribute("StartDate").and_then(parse_date);
+        let end = entry.attribute("EndDate").and_then(parse_date);
+        let full_charge_capacity = entry.attribute("FullChargeCapacity").and_then(parse_mwh);
+        let design_capacity = entry.attribute("DesignCapacity").and_then(parse_mwh);
+
+        if let (Some(start), Some(end), Some(full_charge_capacity), Some(design_capacity)) =
+            (start, end, full_charge_capacity, design_capacity)
+        {
+            report.capacity_history.push(CapacityHistoryRecord {
+                period: ReportPeriod { start, end },
+                full_charge_capacity,
+                design_capacity,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Child elements with the given name, ignoring the namespace
+fn elements<'a, 'input>(node: &Node<'a, 'input>, name: &str) -> Vec<Node<'a, 'input>> {
+    node.children()
+        .filter(|child| child.is_element() && child.tag_name().name() == name)
+        .collect()
+}
+
+fn child_text(node: &Node, name: &str) -> Option<String> {
+    elements(node, name)
+        .first()
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// The capacities are in mWh
+fn parse_mwh(text: &str) -> Option<Capacity> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .map(Capacity::milliwatt_hours)
+}
+
+/// The timestamps are in UTC e.g. "2023-10-19T08:03:21"
+fn parse_timestamp(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+        return Some(date_time.with_timezone(&Utc));
+    }
+
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|date_time| date_time.and_utc())
+}
+
+/// The dates may be with or without time e.g. "2023-10-01" or "2023-10-01T00:00:00"
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text.get(..10)?, "%Y-%m-%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<BatteryReport xmlns="http://schemas.microsoft.com/battery/2012">
+  <Batteries>
+    <Battery>
+      <Id>DELL 7FJ1234</Id>
+      <Manufacturer>SMP</Manufacturer>
+      <Chemistry>LiP</Chemistry>
+      <DesignCapacity>56000</DesignCapacity>
+      <FullChargeCapacity>48500</FullChargeCapacity>
+      <CycleCount>123</CycleCount>
+    </Battery>
+  </Batteries>
+  <RecentUsage>
+    <UsageEntry Timestamp="2023-10-19T08:03:21" Ac="0" ChargeCapacity="40000" Discharge="1" />
+    <UsageEntry Timestamp="2023-10-19T09:00:00" Ac="1" ChargeCapacity="35000" />
+    <UsageEntry Timestamp="2023-10-19T09:30:00.5" ChargeCapacity="38000" />
+    <UsageEntry Timestamp="2023-10-19T10:00:00" Ac="1" />
+  </RecentUsage>
+  <History>
+    <HistoryEntry StartDate="2023-10-01" EndDate="2023-10-08T00:00:00" FullChargeCapacity="49000" DesignCapacity="56000" />
+    <HistoryEntry StartDate="2023-10-08" EndDate="2023-10-15" DesignCapacity="56000" />
+  </History>
+</BatteryReport>
+"#;
+
+    fn date_time(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn batteries_and_capacity_history() {
+        let report = parse_xml_report(REPORT).unwrap();
+
+        assert_eq!(report.batteries.len(), 1);
+        let battery = &report.batteries[0];
+        assert_eq!(battery.id.as_deref(), Some("DELL 7FJ1234"));
+        assert_eq!(battery.serial_number, None);
+        assert_eq!(
+            battery.full_charge_capacity,
+            Some(Capacity::milliwatt_hours(48500.0))
+        );
+        assert_eq!(battery.cycle_count, Some(123));
+
+        // the entry without full charge capacity is skipped
+        assert_eq!(report.capacity_history.len(), 1);
+        let history = &report.capacity_history[0];
+        assert_eq!(
+            (history.period.start, history.period.end),
+            (
+                NaiveDate::from_ymd_opt(2023, 10, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2023, 10, 8).unwrap()
+            )
+        );
+        assert_eq!(
+            history.full_charge_capacity,
+            Capacity::milliwatt_hours(49000.0)
+        );
+    }
+
+    #[test]
+    fn recent_usage_as_time_series() {
+        let history = parse_xml_report(REPORT).unwrap().history;
+
+        // the entry without charge capacity is skipped
+        let records: Vec<(DateTime<Utc>, Capacity, ChargeState)> = history
+            .iter()
+            .map(|record| (record.date_time, record.capacity, record.state))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                (
+                    date_time("2023-10-19T08:03:21Z"),
+                    Capacity::milliwatt_hours(40000.0),
+                    ChargeState::Discharging
+                ),
+                (
+                    date_time("2023-10-19T09:00:00Z"),
+                    Capacity::milliwatt_hours(35000.0),
+                    ChargeState::Charging
+                ),
+                (
+                    date_time("2023-10-19T09:30:00.5Z"),
+                    Capacity::milliwatt_hours(38000.0),
+                    ChargeState::Unknown
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_report() {
+        // unclosed element
+        assert!(matches!(
+            parse_xml_report("<BatteryReport>\n  <RecentUsage>\n</BatteryReport>"),
+            Err(BatteryError::Parse { line: Some(3), .. })
+        ));
+
+        assert!(matches!(
+            parse_xml_report("<BatteryLog />"),
+            Err(BatteryError::Parse { line: None, .. })
+        ));
+
+        let invalid_timestamp = REPORT.replace("2023-10-19T09:00:00", "yesterday");
+        assert!(matches!(
+            parse_xml_report(&invalid_timestamp),
+            Err(BatteryError::Parse { line: Some(15), .. })
+        ));
+    }
+}
@@ -0,0 +1,586 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use std::cmp::Ordering;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionKind {
+    Charging,
+    Discharging,
+    /// the start of the data before the capacity changes for the first time, or the records which
+    /// are reported neither charging nor discharging when segmenting by [TrendSource::ChargeState]
+    Unknown,
+}
+
+/// Continuous run of the battery history in which the battery is either charging or discharging.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub kind: SessionKind,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub start_capacity: Capacity,
+    pub end_capacity: Capacity,
+    /// the records of the session, the first one is shared with the previous session
+    /// so that the curves are connected
+    pub records: BatteryTimeSeries,
+}
+
+impl Session {
+    fn new(kind: SessionKind, records: BatteryTimeSeries) -> Self {
+        let first = records.first().expect("A session has at least one record");
+        let last = records.last().unwrap();
+
+        Session {
+            kind,
+            start: first.date_time,
+            end: last.date_time,
+            start_capacity: first.capacity,
+            end_capacity: last.capacity,
+            records,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Change of the capacity in the unit of the start capacity, negative while discharging.
+    /// [None] when the capacities can't be converted into the same unit.
+    pub fn energy_delta(&self) -> Option<Capacity> {
+        self.end_capacity.checked_sub(self.start_capacity)
+    }
+
+    /// Average change of the capacity per hour in the unit of the start capacity, e.g. mW for mWh.
+    /// [None] for the sessions without duration.
+    pub fn average_rate(&self) -> Option<f64> {
+        let hours = self.duration().num_seconds() as f64 / 3600.0;
+        if hours == 0.0 {
+            return None;
+        }
+
+        Some(self.energy_delta()?.value() / hours)
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.records.len()
+    }
+}
+
+/// How [segment_sessions_with] decides that the trend has changed.
+///
+/// The default starts a new session on every change of the trend, same as [segment_sessions].
+#[derive(Debug, Clone, Default)]
+pub struct SegmentationOptions {
+    /// the capacity must move at least this much against the trend before a new session starts,
+    /// in any unit which the capacities can be converted into
+    pub min_energy_delta: Option<Capacity>,
+    /// the sessions shorter than this are merged into the previous session unless it is of
+    /// [SessionKind::Unknown], e.g. the first session before the capacity changes
+    pub min_duration: Option<Duration>,
+    pub source: TrendSource,
+}
+
+/// What decides whether the battery is charging or discharging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TrendSource {
+    /// the trend of the capacity
+    #[default]
+    Capacity,
+    /// the records reported as [ChargeState::Charging] or [ChargeState::Discharging] decide the kind
+    /// of the session irrespective of the capacity, the trend of the capacity is only used for the other records
+    ChargeStateOrCapacity,
+    /// only the reported state, a new session starts whenever it changes and the minimum energy delta is ignored
+    ChargeState,
+}
+
+impl TrendSource {
+    /// The kind of session given record belongs to irrespective of the capacity, if any
+    fn reported_kind(&self, record: &BatteryHistoryRecord) -> Option<SessionKind> {
+        match (self, record.state) {
+            (TrendSource::Capacity, _) => None,
+            (_, ChargeState::Charging) => Some(SessionKind::Charging),
+            (_, ChargeState::Discharging) => Some(SessionKind::Discharging),
+            (TrendSource::ChargeStateOrCapacity, _) => None,
+            (TrendSource::ChargeState, _) => Some(SessionKind::Unknown),
+        }
+    }
+}
+
+/// Session which is still being built, along with its latest highest and lowest records
+struct Run {
+    kind: SessionKind,
+    records: BatteryTimeSeries,
+    high: BatteryHistoryRecord,
+    low: BatteryHistoryRecord,
+}
+
+impl Run {
+    fn new(kind: SessionKind, records: BatteryTimeSeries) -> Self {
+        let first = records
+            .first()
+            .expect("A session has at least one record")
+            .clone();
+        let mut run = Run {
+            kind,
+            records: BatteryTimeSeries::new(),
+            high: first.clone(),
+            low: first,
+        };
+        records.into_iter().for_each(|record| run.push(record));
+        run
+    }
+
+    fn push(&mut self, record: BatteryHistoryRecord) {
+        if record.capacity >= self.high.capacity {
+            self.high = record.clone();
+        }
+        if record.capacity <= self.low.capacity {
+            self.low = record.clone();
+        }
+        self.records.insert(record);
+    }
+
+    /// Removes the records after given time, which along with the record at that time start a new run
+    fn split_off(&mut self, kind: SessionKind, date_time: DateTime<Utc>) -> Run {
+        let records = self.records.slice(date_time..);
+        self.records.retain(|record| record.date_time <= date_time);
+        Run::new(kind, records)
+    }
+
+    fn duration(&self) -> Duration {
+        match (self.records.first(), self.records.last()) {
+            (Some(first), Some(last)) => last.date_time - first.date_time,
+            _ => Duration::zero(),
+        }
+    }
+}
+
+/// Splits the data into charging and discharging sessions on every change of the trend of the capacity.
+///
+/// # Returns
+/// The sessions in time order, starting with a [SessionKind::Unknown] session
+/// up to the first change of the capacity. Empty when the data is empty.
+pub fn segment_sessions(data: &BatteryTimeSeries) -> Vec<Session> {
+    segment(data, &SegmentationOptions::default())
+}
+
+/// Same as [segment_sessions], but the small changes of the capacity against the trend and the short
+/// sessions are ignored as described by the options, so that the sessions follow the plug and unplug events
+/// rather than the noise of the sensor or the interpolation.
+///
+/// # Returns
+/// The sessions in time order, or the error when the minimum energy delta is used by the source
+/// and can't be converted into the unit of the capacities.
+pub fn segment_sessions_with(
+    data: &BatteryTimeSeries,
+    options: &SegmentationOptions,
+) -> Result<Vec<Session>, BatteryError> {
+    if let (Some(min_energy_delta), Some(first)) = (options.min_energy_delta, data.first()) {
+        if options.source != TrendSource::ChargeState
+            && min_energy_delta.to(first.capacity.unit()).is_none()
+        {
+            return Err(BatteryError::IncompatibleUnits {
+                from: min_energy_delta.unit(),
+                to: first.capacity.unit(),
+            });
+        }
+    }
+
+    Ok(segment(data, options))
+}
+
+fn segment(data: &BatteryTimeSeries, options: &SegmentationOptions) -> Vec<Session> {
+    let mut records = data.iter();
+
+    let mut prev_record = match records.next() {
+        Some(record) => record,
+        None => return Vec::new(),
+    };
+
+    let mut runs: Vec<Run> = vec![Run::new(
+        options
+            .source
+            .reported_kind(prev_record)
+            .unwrap_or(SessionKind::Unknown),
+        BatteryTimeSeries::from_iter([prev_record.clone()]),
+    )];
+
+    // whether the capacity has risen from `from` to `to` by more than the noise
+    let rises = |from: &BatteryHistoryRecord, to: &BatteryHistoryRecord| match to
+        .capacity
+        .checked_sub(from.capacity)
+    {
+        Some(delta) => {
+            delta.value() > 0.0
+                && options
+                    .min_energy_delta
+                    .map_or(true, |min_energy_delta| delta >= min_energy_delta)
+        }
+        None => false,
+    };
+
+    for record in records {
+        let run = runs.last_mut().unwrap();
+
+        match (options.source.reported_kind(record), run.kind) {
+            (Some(kind), current_kind) if kind != current_kind => {
+                // the previous value starts the new session to make the graph connected
+                runs.push(Run::new(
+                    kind,
+                    BatteryTimeSeries::from_iter([prev_record.clone(), record.clone()]),
+                ));
+            }
+            (Some(_), _) => run.push(record.clone()),
+            (None, SessionKind::Unknown) => {
+                run.push(record.clone());
+
+                // the current value starts the new session for connecting the dots otherwise the curve will be disconnected
+                let kind = if rises(&run.low, record) {
+                    Some(SessionKind::Charging)
+                } else if rises(record, &run.high) {
+                    Some(SessionKind::Discharging)
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    runs.push(Run::new(
+                        kind,
+                        BatteryTimeSeries::from_iter([record.clone()]),
+                    ));
+                }
+            }
+            (None, SessionKind::Charging) => {
+                // the new session starts from the highest point, once the capacity has fallen enough from it
+                if rises(record, &run.high) {
+                    let mut next = run.split_off(SessionKind::Discharging, run.high.date_time);
+                    next.push(record.clone());
+                    runs.push(next);
+                } else {
+                    run.push(record.clone());
+                }
+            }
+            (None, SessionKind::Discharging) => {
+                // the new session starts from the lowest point, once the capacity has risen enough from it
+                if rises(&run.low, record) {
+                    let mut next = run.split_off(SessionKind::Charging, run.low.date_time);
+                    next.push(record.clone());
+                    runs.push(next);
+                } else {
+                    run.push(record.clone());
+                }
+            }
+        }
+        prev_record = record;
+    }
+
+    if let Some(min_duration) = options.min_duration {
+        let mut merged_runs: Vec<Run> = Vec::new();
+
+        for run in runs {
+            match merged_runs.last_mut() {
+                // a charging or discharging run isn't lost in the unknown run before it
+                Some(previous)
+                    if previous.kind == run.kind
+                        || (run.duration() < min_duration
+                            && previous.kind != SessionKind::Unknown) =>
+                {
+                    run.records
+                        .into_iter()
+                        .for_each(|record| previous.push(record));
+                }
+                _ => merged_runs.push(run),
+            }
+        }
+
+        runs = merged_runs;
+    }
+
+    runs.into_iter()
+        .map(|run| Session::new(run.kind, run.records))
+        .collect()
+}
+
+/// Span of the history in which the trend of the capacity contradicts the reported state.
+#[derive(Debug, Clone)]
+pub struct StateDisagreement {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// the state reported at the start of the span
+    pub reported: ChargeState,
+    /// the kind of session suggested by the trend of the capacity
+    pub observed: SessionKind,
+    pub start_capacity: Capacity,
+    pub end_capacity: Capacity,
+}
+
+/// Finds where the capacity rises while the battery is reported discharging, or falls while it is
+/// reported charging, e.g. for checking a log before segmenting it by [TrendSource::ChargeState].
+///
+/// The state of a record is taken to hold until the next record, the consecutive disagreeing
+/// records are reported as a single span.
+pub fn find_state_disagreements(data: &BatteryTimeSeries) -> Vec<StateDisagreement> {
+    let mut disagreements: Vec<StateDisagreement> = Vec::new();
+    let records: Vec<&BatteryHistoryRecord> = data.iter().collect();
+
+    for pair in records.windows(2) {
+        let (prev_record, record) = (pair[0], pair[1]);
+
+        let observed = match (
+            prev_record.state,
+            record.capacity.partial_cmp(&prev_record.capacity),
+        ) {
+            (ChargeState::Charging, Some(Ordering::Less)) => SessionKind::Discharging,
+            (ChargeState::Discharging, Some(Ordering::Greater)) => SessionKind::Charging,
+            _ => continue,
+        };
+
+        match disagreements.last_mut() {
+            Some(last)
+                if last.end == prev_record.date_time && last.reported == prev_record.state =>
+            {
+                last.end = record.date_time;
+                last.end_capacity = record.capacity;
+            }
+            _ => disagreements.push(StateDisagreement {
+                start: prev_record.date_time,
+                end: record.date_time,
+                reported: prev_record.state,
+                observed,
+                start_capacity: prev_record.capacity,
+                end_capacity: record.capacity,
+            }),
+        }
+    }
+
+    disagreements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{minute, per_minute};
+
+    fn unknown(capacities: &[f64]) -> BatteryTimeSeries {
+        per_minute(
+            &capacities
+                .iter()
+                .map(|capacity| (*capacity, ChargeState::Unknown))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn kinds(sessions: &[Session]) -> Vec<SessionKind> {
+        sessions.iter().map(|session| session.kind).collect()
+    }
+
+    // discharging with a small rise at minute 3, then charging from minute 6
+    const NOISY: [f64; 9] = [100.0, 98.0, 96.0, 97.0, 94.0, 92.0, 95.0, 98.0, 101.0];
+
+    #[test]
+    fn every_change_of_the_trend_without_hysteresis() {
+        let sessions = segment_sessions(&unknown(&NOISY));
+
+        assert_eq!(
+            kinds(&sessions),
+            [
+                SessionKind::Unknown,
+                SessionKind::Discharging,
+                SessionKind::Charging,
+                SessionKind::Discharging,
+                SessionKind::Charging
+            ]
+        );
+    }
+
+    #[test]
+    fn hysteresis_holds_the_state_through_noise() {
+        let options = SegmentationOptions {
+            min_energy_delta: Some(Capacity::milliwatt_hours(2.0)),
+            ..SegmentationOptions::default()
+        };
+        let sessions = segment_sessions_with(&unknown(&NOISY), &options).unwrap();
+
+        assert_eq!(
+            kinds(&sessions),
+            [
+                SessionKind::Unknown,
+                SessionKind::Discharging,
+                SessionKind::Charging
+            ]
+        );
+        // the charging session starts from the lowest point
+        assert_eq!(sessions[1].end, minute(5));
+        assert_eq!(sessions[1].end_capacity, Capacity::milliwatt_hours(92.0));
+        assert_eq!(sessions[2].start, minute(5));
+        assert_eq!(sessions[2].end_capacity, Capacity::milliwatt_hours(101.0));
+    }
+
+    #[test]
+    fn short_sessions_are_merged() {
+        let options = SegmentationOptions {
+            min_duration: Some(Duration::minutes(2)),
+            ..SegmentationOptions::default()
+        };
+        let sessions = segment_sessions_with(&unknown(&NOISY), &options).unwrap();
+
+        // the one minute rise is merged into the discharging session, which isn't merged
+        // into the unknown session before it even though its first part is one minute long
+        assert_eq!(
+            spans(&sessions),
+            [
+                (SessionKind::Unknown, 0, 1),
+                (SessionKind::Discharging, 1, 5),
+                (SessionKind::Charging, 5, 8)
+            ]
+        );
+        assert_eq!(sessions[0].end_capacity, Capacity::milliwatt_hours(98.0));
+        assert_eq!(sessions[1].start_capacity, Capacity::milliwatt_hours(98.0));
+        assert_eq!(sessions[1].end_capacity, Capacity::milliwatt_hours(92.0));
+        assert_eq!(sessions[2].end_capacity, Capacity::milliwatt_hours(101.0));
+    }
+
+    #[test]
+    fn incompatible_minimum_energy_delta() {
+        let options = SegmentationOptions {
+            min_energy_delta: Some(Capacity::percent(1.0)),
+            ..SegmentationOptions::default()
+        };
+
+        assert!(matches!(
+            segment_sessions_with(&unknown(&NOISY), &options),
+            Err(BatteryError::IncompatibleUnits { .. })
+        ));
+
+        // the delta isn't used with the reported state alone
+        let options = SegmentationOptions {
+            source: TrendSource::ChargeState,
+            ..options
+        };
+        assert!(segment_sessions_with(&per_minute(&MIXED), &options).is_ok());
+    }
+
+    fn spans(sessions: &[Session]) -> Vec<(SessionKind, i64, i64)> {
+        sessions
+            .iter()
+            .map(|session| {
+                (
+                    session.kind,
+                    (session.start - minute(0)).num_minutes(),
+                    (session.end - minute(0)).num_minutes(),
+                )
+            })
+            .collect()
+    }
+
+    // reported discharging, then unknown while the capacity turns, then reported charging
+    const MIXED: [(f64, ChargeState); 6] = [
+        (50.0, ChargeState::Discharging),
+        (48.0, ChargeState::Discharging),
+        (46.0, ChargeState::Unknown),
+        (48.0, ChargeState::Unknown),
+        (50.0, ChargeState::Charging),
+        (52.0, ChargeState::Charging),
+    ];
+
+    fn segment_by(data: &BatteryTimeSeries, source: TrendSource) -> Vec<Session> {
+        let options = SegmentationOptions {
+            source,
+            ..SegmentationOptions::default()
+        };
+        segment_sessions_with(data, &options).unwrap()
+    }
+
+    #[test]
+    fn trend_source_capacity_ignores_the_logged_state() {
+        assert_eq!(
+            spans(&segment_by(&per_minute(&MIXED), TrendSource::Capacity)),
+            [
+                (SessionKind::Unknown, 0, 1),
+                (SessionKind::Discharging, 1, 2),
+                (SessionKind::Charging, 2, 5)
+            ]
+        );
+
+        // the logged state alone doesn't make a session
+        let flat = per_minute(&[
+            (50.0, ChargeState::Discharging),
+            (50.0, ChargeState::Discharging),
+            (50.0, ChargeState::Charging),
+            (50.0, ChargeState::Charging),
+        ]);
+        assert_eq!(
+            spans(&segment_by(&flat, TrendSource::Capacity)),
+            [(SessionKind::Unknown, 0, 3)]
+        );
+        assert_eq!(
+            spans(&segment_by(&flat, TrendSource::ChargeState)),
+            [
+                (SessionKind::Discharging, 0, 1),
+                (SessionKind::Charging, 1, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn trend_source_charge_state_follows_the_logged_state() {
+        assert_eq!(
+            spans(&segment_by(&per_minute(&MIXED), TrendSource::ChargeState)),
+            [
+                (SessionKind::Discharging, 0, 1),
+                (SessionKind::Unknown, 1, 3),
+                (SessionKind::Charging, 3, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn trend_source_charge_state_or_capacity_fills_the_unknown_states() {
+        assert_eq!(
+            spans(&segment_by(
+                &per_minute(&MIXED),
+                TrendSource::ChargeStateOrCapacity
+            )),
+            [
+                (SessionKind::Discharging, 0, 2),
+                (SessionKind::Charging, 2, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn state_disagreements() {
+        let data = per_minute(&[
+            (50.0, ChargeState::Charging),
+            (48.0, ChargeState::Charging),
+            (49.0, ChargeState::Discharging),
+            (50.0, ChargeState::Discharging),
+            (51.0, ChargeState::Discharging),
+        ]);
+
+        let disagreements = find_state_disagreements(&data);
+
+        assert_eq!(disagreements.len(), 2);
+        assert_eq!(disagreements[0].reported, ChargeState::Charging);
+        assert_eq!(disagreements[0].observed, SessionKind::Discharging);
+        assert_eq!(
+            (disagreements[0].start, disagreements[0].end),
+            (minute(0), minute(1))
+        );
+        assert_eq!(disagreements[1].reported, ChargeState::Discharging);
+        assert_eq!(disagreements[1].observed, SessionKind::Charging);
+        assert_eq!(
+            (disagreements[1].start, disagreements[1].end),
+            (minute(2), minute(4))
+        );
+        assert_eq!(
+            disagreements[1].end_capacity,
+            Capacity::milliwatt_hours(51.0)
+        );
+    }
+}
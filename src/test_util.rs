@@ -0,0 +1,102 @@
+//! Fixtures shared by the tests of the modules.
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::read_data::BatteryHistoryRecord;
+use crate::read_data::ChargeState;
+use crate::time_series::BatteryTimeSeries;
+use crate::units::Capacity;
+
+/// This many minutes after 2023-10-20 08:00 UTC, the time of the first record of the fixtures
+pub(crate) fn minute(minutes: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(1697788800, 0).unwrap() + Duration::minutes(minutes)
+}
+
+/// This many hours after the first record of the fixtures
+pub(crate) fn hour(hours: i64) -> DateTime<Utc> {
+    minute(60 * hours)
+}
+
+/// The records one `step` apart from [minute] 0.
+pub(crate) fn series<I: IntoIterator<Item = (Capacity, ChargeState)>>(
+    step: Duration,
+    records: I,
+) -> BatteryTimeSeries {
+    let mut date_time = minute(0);
+    let mut series = BatteryTimeSeries::new();
+
+    for (capacity, state) in records {
+        series.insert(BatteryHistoryRecord {
+            date_time,
+            capacity,
+            state,
+        });
+        date_time += step;
+    }
+
+    series
+}
+
+/// A record every minute, the capacities are in mWh
+pub(crate) fn per_minute(records: &[(f64, ChargeState)]) -> BatteryTimeSeries {
+    milliwatt_hours(Duration::minutes(1), records)
+}
+
+/// A record every hour, the capacities are in mWh
+pub(crate) fn per_hour(records: &[(f64, ChargeState)]) -> BatteryTimeSeries {
+    milliwatt_hours(Duration::hours(1), records)
+}
+
+fn milliwatt_hours(step: Duration, records: &[(f64, ChargeState)]) -> BatteryTimeSeries {
+    series(
+        step,
+        records
+            .iter()
+            .map(|(capacity, state)| (Capacity::milliwatt_hours(*capacity), *state)),
+    )
+}
+
+/// Directory of the files of a test, removed when dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// The name tells apart the tests running at the same time
+    pub(crate) fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "battery-data-analysis-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        TempDir(root)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes the file into the directory, along with the directories of its path
+    ///
+    /// # Returns
+    /// The path of the file.
+    pub(crate) fn write(&self, file: &str, contents: &str) -> String {
+        let path = self.0.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
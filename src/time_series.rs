@@ -0,0 +1,220 @@
+use chrono::DateTime;
+use chrono::Utc;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ops::RangeBounds;
+
+use crate::error::BatteryError;
+use crate::read_data::BatteryHistoryRecord;
+use crate::units::Capacity;
+use crate::units::CapacityUnit;
+
+/// Battery history ordered by the time of the records, with at most one record at any time.
+///
+/// Lookups and range queries by time are O(log n) and the iteration is always in time order,
+/// so there is no need of sorting the data before plotting or segmenting it.
+#[derive(Debug, Clone, Default)]
+pub struct BatteryTimeSeries {
+    records: BTreeMap<DateTime<Utc>, BatteryHistoryRecord>,
+}
+
+impl BatteryTimeSeries {
+    pub fn new() -> Self {
+        BatteryTimeSeries {
+            records: BTreeMap::new(),
+        }
+    }
+
+    /// Inserts the record at its date_time, returning the record which was previously at that time.
+    pub fn insert(&mut self, record: BatteryHistoryRecord) -> Option<BatteryHistoryRecord> {
+        self.records.insert(record.date_time, record)
+    }
+
+    pub fn remove(&mut self, date_time: &DateTime<Utc>) -> Option<BatteryHistoryRecord> {
+        self.records.remove(date_time)
+    }
+
+    pub fn get(&self, date_time: &DateTime<Utc>) -> Option<&BatteryHistoryRecord> {
+        self.records.get(date_time)
+    }
+
+    /// The latest record at or before given time
+    pub fn at_or_before(&self, date_time: DateTime<Utc>) -> Option<&BatteryHistoryRecord> {
+        self.records
+            .range(..=date_time)
+            .next_back()
+            .map(|(_, record)| record)
+    }
+
+    /// The earliest record at or after given time
+    pub fn at_or_after(&self, date_time: DateTime<Utc>) -> Option<&BatteryHistoryRecord> {
+        self.records
+            .range(date_time..)
+            .next()
+            .map(|(_, record)| record)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn first(&self) -> Option<&BatteryHistoryRecord> {
+        self.records.values().next()
+    }
+
+    pub fn last(&self) -> Option<&BatteryHistoryRecord> {
+        self.records.values().next_back()
+    }
+
+    /// Iterates over the records in time order
+    pub fn iter(&self) -> btree_map::Values<'_, DateTime<Utc>, BatteryHistoryRecord> {
+        self.records.values()
+    }
+
+    /// Iterates over the records in given time range in time order, e.g. `series.range(start..end)`
+    pub fn range<R: RangeBounds<DateTime<Utc>>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = &BatteryHistoryRecord> {
+        self.records.range(range).map(|(_, record)| record)
+    }
+
+    /// Copy of the records in given time range
+    pub fn slice<R: RangeBounds<DateTime<Utc>>>(&self, range: R) -> BatteryTimeSeries {
+        self.range(range).cloned().collect()
+    }
+
+    /// Only keeps the records for which the predicate returns true
+    pub fn retain<F: FnMut(&BatteryHistoryRecord) -> bool>(&mut self, mut predicate: F) {
+        self.records.retain(|_, record| predicate(record));
+    }
+
+    /// Adds all the records of other, the records of other replace the ones at the same time.
+    pub fn merge(&mut self, other: BatteryTimeSeries) {
+        self.records.extend(other.records);
+    }
+
+    /// The times of the records in order
+    pub fn dates(&self) -> Vec<DateTime<Utc>> {
+        self.records.keys().copied().collect()
+    }
+
+    /// The capacities of the records in time order
+    pub fn capacities(&self) -> Vec<Capacity> {
+        self.records
+            .values()
+            .map(|record| record.capacity)
+            .collect()
+    }
+
+    /// The same records with their capacities converted into given unit,
+    /// so that the records of different sources can be compared.
+    pub fn to_unit(&self, unit: CapacityUnit) -> Result<BatteryTimeSeries, BatteryError> {
+        self.iter()
+            .map(|record| {
+                Ok(BatteryHistoryRecord {
+                    capacity: record
+                        .capacity
+                        .to(unit)
+                        .ok_or(BatteryError::IncompatibleUnits {
+                            from: record.capacity.unit(),
+                            to: unit,
+                        })?,
+                    ..record.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+impl FromIterator<BatteryHistoryRecord> for BatteryTimeSeries {
+    fn from_iter<I: IntoIterator<Item = BatteryHistoryRecord>>(iter: I) -> Self {
+        let mut series = BatteryTimeSeries::new();
+        series.extend(iter);
+        series
+    }
+}
+
+impl Extend<BatteryHistoryRecord> for BatteryTimeSeries {
+    fn extend<I: IntoIterator<Item = BatteryHistoryRecord>>(&mut self, iter: I) {
+        for record in iter {
+            self.insert(record);
+        }
+    }
+}
+
+/// The records are kept at their own date_time, irrespective of the keys of the map.
+impl From<HashMap<DateTime<Utc>, BatteryHistoryRecord>> for BatteryTimeSeries {
+    fn from(data: HashMap<DateTime<Utc>, BatteryHistoryRecord>) -> Self {
+        data.into_values().collect()
+    }
+}
+
+impl IntoIterator for BatteryTimeSeries {
+    type Item = BatteryHistoryRecord;
+    type IntoIter = btree_map::IntoValues<DateTime<Utc>, BatteryHistoryRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.into_values()
+    }
+}
+
+impl<'a> IntoIterator for &'a BatteryTimeSeries {
+    type Item = &'a BatteryHistoryRecord;
+    type IntoIter = btree_map::Values<'a, DateTime<Utc>, BatteryHistoryRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_data::ChargeState;
+    use crate::test_util;
+    use chrono::Duration;
+
+    fn series(capacities: &[Capacity]) -> BatteryTimeSeries {
+        test_util::series(
+            Duration::minutes(1),
+            capacities
+                .iter()
+                .map(|capacity| (*capacity, ChargeState::Discharging)),
+        )
+    }
+
+    #[test]
+    fn to_unit_converts_every_record() {
+        let converted = series(&[Capacity::joules(3600.0), Capacity::watt_hours(2.0)])
+            .to_unit(CapacityUnit::MilliwattHour)
+            .unwrap();
+
+        assert!(converted
+            .iter()
+            .all(|record| record.capacity.unit() == CapacityUnit::MilliwattHour));
+        assert_eq!(
+            converted.last().unwrap().capacity,
+            Capacity::milliwatt_hours(2000.0)
+        );
+    }
+
+    #[test]
+    fn to_unit_incompatible_units() {
+        let result = series(&[Capacity::milliwatt_hours(1000.0), Capacity::percent(50.0)])
+            .to_unit(CapacityUnit::MilliwattHour);
+
+        match result {
+            Err(BatteryError::IncompatibleUnits { from, to }) => {
+                assert_eq!(from, CapacityUnit::Percent);
+                assert_eq!(to, CapacityUnit::MilliwattHour);
+            }
+            result => panic!("Expected incompatible units, found {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Units in which the battery capacity is reported by the various sources.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum CapacityUnit {
+    #[serde(rename = "mWh")]
+    MilliwattHour,
+    #[serde(rename = "Wh")]
+    WattHour,
+    #[serde(rename = "J")]
+    Joule,
+    #[serde(rename = "mAh")]
+    MilliampHour,
+    #[serde(rename = "%")]
+    Percent,
+}
+
+impl CapacityUnit {
+    /// Energy units can be converted between each other without any other information
+    pub fn is_energy(&self) -> bool {
+        matches!(
+            self,
+            CapacityUnit::MilliwattHour | CapacityUnit::WattHour | CapacityUnit::Joule
+        )
+    }
+
+    /// mWh in one of this unit, [None] for the units which aren't energy
+    fn milliwatt_hours(&self) -> Option<f64> {
+        match self {
+            CapacityUnit::MilliwattHour => Some(1.0),
+            CapacityUnit::WattHour => Some(1000.0),
+            CapacityUnit::Joule => Some(1.0 / 3.6),
+            CapacityUnit::MilliampHour | CapacityUnit::Percent => None,
+        }
+    }
+}
+
+impl fmt::Display for CapacityUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            CapacityUnit::MilliwattHour => "mWh",
+            CapacityUnit::WattHour => "Wh",
+            CapacityUnit::Joule => "J",
+            CapacityUnit::MilliampHour => "mAh",
+            CapacityUnit::Percent => "%",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+/// Energy or charge stored in the battery along with its unit.
+///
+/// Conversions are explicit: energy units convert freely with [Capacity::to], charge needs
+/// the voltage ([Capacity::to_with_voltage]) and percentage needs the full capacity ([Capacity::of]).
+/// Capacities in different units are only comparable when they can be converted without any other information.
+#[derive(Clone, Copy, Debug)]
+pub struct Capacity {
+    value: f64,
+    unit: CapacityUnit,
+}
+
+impl Capacity {
+    pub fn new(value: f64, unit: CapacityUnit) -> Self {
+        Capacity { value, unit }
+    }
+
+    pub fn milliwatt_hours(value: f64) -> Self {
+        Capacity::new(value, CapacityUnit::MilliwattHour)
+    }
+
+    pub fn watt_hours(value: f64) -> Self {
+        Capacity::new(value, CapacityUnit::WattHour)
+    }
+
+    pub fn joules(value: f64) -> Self {
+        Capacity::new(value, CapacityUnit::Joule)
+    }
+
+    pub fn milliamp_hours(value: f64) -> Self {
+        Capacity::new(value, CapacityUnit::MilliampHour)
+    }
+
+    pub fn percent(value: f64) -> Self {
+        Capacity::new(value, CapacityUnit::Percent)
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn unit(&self) -> CapacityUnit {
+        self.unit
+    }
+
+    /// Converts into given unit, [None] when the conversion needs the voltage or the full capacity.
+    pub fn to(&self, unit: CapacityUnit) -> Option<Capacity> {
+        if self.unit == unit {
+            return Some(*self);
+        }
+
+        let from = self.unit.milliwatt_hours()?;
+        let to = unit.milliwatt_hours()?;
+        Some(Capacity::new(self.value * from / to, unit))
+    }
+
+    /// Converts between charge and energy at given voltage in mV, other conversions are same as [Capacity::to].
+    pub fn to_with_voltage(self, unit: CapacityUnit, millivolts: f64) -> Option<Capacity> {
+        match (self.unit, unit) {
+            (CapacityUnit::MilliampHour, CapacityUnit::MilliampHour) => Some(self),
+            (CapacityUnit::MilliampHour, _) => {
+                Capacity::milliwatt_hours(self.value * millivolts / 1000.0).to(unit)
+            }
+            (_, CapacityUnit::MilliampHour) => {
+                let energy = self.to(CapacityUnit::MilliwattHour)?;
+                Some(Capacity::milliamp_hours(energy.value * 1000.0 / millivolts))
+            }
+            _ => self.to(unit),
+        }
+    }
+
+    /// Converts a percentage into the unit of given full capacity
+    pub fn of(&self, full: Capacity) -> Option<Capacity> {
+        match self.unit {
+            CapacityUnit::Percent => {
+                Some(Capacity::new(full.value * self.value / 100.0, full.unit))
+            }
+            _ => None,
+        }
+    }
+
+    /// Percentage of given full capacity, [None] when they can't be converted into the same unit
+    pub fn percent_of(&self, full: Capacity) -> Option<Capacity> {
+        let full = full.to(self.unit)?;
+        Some(Capacity::percent(self.value * 100.0 / full.value))
+    }
+
+    /// Sum in the unit of self, [None] when other can't be converted into it
+    pub fn checked_add(&self, other: Capacity) -> Option<Capacity> {
+        Some(Capacity::new(
+            self.value + other.to(self.unit)?.value,
+            self.unit,
+        ))
+    }
+
+    /// Difference in the unit of self, [None] when other can't be converted into it
+    pub fn checked_sub(&self, other: Capacity) -> Option<Capacity> {
+        Some(Capacity::new(
+            self.value - other.to(self.unit)?.value,
+            self.unit,
+        ))
+    }
+}
+
+impl PartialEq for Capacity {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Capacity {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.to(self.unit)?.value)
+    }
+}
+
+impl fmt::Display for Capacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+/// (De)serializes the capacity as a number of mWh, used for the `capacity` column of the history csv.
+pub mod as_milliwatt_hours {
+    use super::{Capacity, CapacityUnit};
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(capacity: &Capacity, serializer: S) -> Result<S::Ok, S::Error> {
+        let capacity = capacity
+            .to(CapacityUnit::MilliwattHour)
+            .ok_or_else(|| S::Error::custom(format!("Can't write {capacity} as mWh")))?;
+
+        // whole numbers are written without the decimal point, like the csv read by get_data
+        if capacity.value().fract() == 0.0 {
+            serializer.serialize_i64(capacity.value() as i64)
+        } else {
+            serializer.serialize_f64(capacity.value())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Capacity, D::Error> {
+        let value = f64::deserialize(deserializer)?;
+        if value.is_finite() {
+            Ok(Capacity::milliwatt_hours(value))
+        } else {
+            Err(D::Error::custom(format!("Invalid capacity {value}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(capacity: Option<Capacity>, value: f64, unit: CapacityUnit) {
+        let capacity = capacity.unwrap();
+        assert_eq!(capacity.unit(), unit);
+        assert!(
+            (capacity.value() - value).abs() < 1e-9,
+            "Expected {value} {unit}, found {capacity:?}"
+        );
+    }
+
+    #[test]
+    fn energy_conversions() {
+        let capacity = Capacity::milliwatt_hours(1000.0);
+
+        assert_close(
+            capacity.to(CapacityUnit::Joule),
+            3600.0,
+            CapacityUnit::Joule,
+        );
+        assert_close(
+            capacity.to(CapacityUnit::WattHour),
+            1.0,
+            CapacityUnit::WattHour,
+        );
+        assert_close(
+            Capacity::joules(7200.0).to(CapacityUnit::WattHour),
+            2.0,
+            CapacityUnit::WattHour,
+        );
+        assert_close(
+            Capacity::joules(7200.0).to(CapacityUnit::MilliwattHour),
+            2000.0,
+            CapacityUnit::MilliwattHour,
+        );
+        assert_close(
+            Capacity::watt_hours(0.5).to(CapacityUnit::Joule),
+            1800.0,
+            CapacityUnit::Joule,
+        );
+        assert_eq!(Capacity::joules(3600.0), Capacity::watt_hours(1.0));
+    }
+
+    #[test]
+    fn percent_and_charge_need_more_information() {
+        assert!(Capacity::percent(50.0)
+            .to(CapacityUnit::MilliwattHour)
+            .is_none());
+        assert!(Capacity::milliwatt_hours(1000.0)
+            .to(CapacityUnit::Percent)
+            .is_none());
+        assert!(Capacity::milliamp_hours(1000.0)
+            .to(CapacityUnit::Joule)
+            .is_none());
+        assert!(Capacity::percent(50.0)
+            .checked_add(Capacity::milliwatt_hours(1.0))
+            .is_none());
+        assert_ne!(Capacity::percent(50.0), Capacity::milliwatt_hours(50.0));
+
+        assert_close(
+            Capacity::percent(50.0).of(Capacity::watt_hours(60.0)),
+            30.0,
+            CapacityUnit::WattHour,
+        );
+        assert_close(
+            Capacity::milliwatt_hours(15000.0).percent_of(Capacity::watt_hours(60.0)),
+            25.0,
+            CapacityUnit::Percent,
+        );
+        assert_close(
+            Capacity::milliamp_hours(2000.0).to_with_voltage(CapacityUnit::WattHour, 12000.0),
+            24.0,
+            CapacityUnit::WattHour,
+        );
+        assert_close(
+            Capacity::watt_hours(24.0).to_with_voltage(CapacityUnit::MilliampHour, 12000.0),
+            2000.0,
+            CapacityUnit::MilliampHour,
+        );
+    }
+}